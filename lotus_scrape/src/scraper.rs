@@ -1,11 +1,10 @@
 mod scrape_writer;
 mod scraper_types;
 
-use crate::SCRAPER_HEADING;
 use const_format::formatcp;
 use http::HeaderMap;
 use lazy_static::lazy_static;
-use lotus::OutputFiles;
+use lotus::{config, OutputFiles};
 use regex::Regex;
 use reqwest::blocking::{self, Client, Response};
 use scraper::{Html, Selector};
@@ -17,8 +16,9 @@ use std::{
         Arc,
     },
     thread::{self, Scope},
-    time::Duration,
+    time::{Duration, SystemTime},
 };
+use tracing::{debug, error, info, info_span};
 
 /// Number of times to try a url before giving up. In reality, urls may be tried more than this
 /// in rare circumstances.
@@ -70,8 +70,8 @@ pub struct Scraper {
 impl Scraper {
     pub fn new() -> Scraper {
         Scraper {
-            max_concurrent_requests: 8,
-            download_delay: 0,
+            max_concurrent_requests: config::DEFAULT_CONCURRENT_REQUESTS,
+            download_delay: config::DEFAULT_DOWNLOAD_DELAY,
         }
     }
 
@@ -94,19 +94,21 @@ impl Scraper {
         tag_pages: Vec<&str>,
         outputs: OutputFiles,
     ) -> Result<(), ScrapeError> {
-        eprintln!("{}Getting page list", SCRAPER_HEADING);
+        let started = SystemTime::now();
+
+        info!("Getting page list");
         let mut scrape_list = self.create_page_list(tag_pages)?;
 
         // Limit the number of pages (debugging, mostly)
         scrape_list.truncate(article_limit);
 
-        eprintln!("{}Getting the list of tags", SCRAPER_HEADING);
+        info!(pages = scrape_list.len(), "Getting the list of tags");
         let tag_group = self.scrape_all_tags()?;
 
-        eprintln!("{}Scraping the pages", SCRAPER_HEADING);
+        info!(tags = tag_group.len(), "Scraping the pages");
         let scraped_info = self.scrape_pages(scrape_list, tag_group)?;
 
-        scrape_writer::record_info(scraped_info, outputs)?;
+        scrape_writer::record_info(scraped_info, outputs, started)?;
 
         Ok(())
     }
@@ -152,7 +154,7 @@ impl Scraper {
         client: &blocking::Client,
         listpages: Vec<&'static str>,
     ) -> Result<HashMap<String, String>, ScrapeError> {
-        eprintln!("{}Getting SCP names from ListPages", SCRAPER_HEADING);
+        info!("Getting SCP names from ListPages");
         let mut name_map = HashMap::new();
         let selector = Selector::parse("h1~ul>li").expect("Hardcoded selector should not fail");
         for listpage in listpages {
@@ -259,9 +261,9 @@ impl Scraper {
         let mut tag_collection = Vec::new();
         let client = blocking::Client::new();
 
-        eprintln!("{}Making tags page request", SCRAPER_HEADING);
+        debug!("Making tags page request");
         let response = self.retry_get_request(&client, TAG_PREFIX)?;
-        eprintln!("{}Getting tags page response", SCRAPER_HEADING);
+        debug!("Getting tags page response");
         let document = Html::parse_document(response.text()?.as_str());
         let page_item = Selector::parse(".tag").expect("Hardcoded selector shouldn't fail");
         let page_elements = document.select(&page_item);
@@ -294,7 +296,7 @@ impl Scraper {
         let tags_arc = Arc::new(&mut tags);
 
         // Create the threads
-        eprintln!("{}Creating the threads...", SCRAPER_HEADING);
+        info!(threads = num_threads, "Creating the threads");
         lazy_static::initialize(&PAGE_ID_PATTERN);
         lazy_static::initialize(&USER_PATTERN);
         thread::scope(|scope| {
@@ -304,7 +306,7 @@ impl Scraper {
                 self.spawn_scraper_thread(&scope, main_tx, id, thread_rx, tags_copy);
             }
 
-            eprintln!("{}Actually scraping the pages...", SCRAPER_HEADING);
+            info!(pages = num_articles, "Actually scraping the pages");
             run_messaging(
                 &mut articles,
                 num_articles,
@@ -368,6 +370,7 @@ impl Scraper {
             Selector::parse(r#"div.page-tags a"#).expect("Hardcoded selector should not fail");
 
         scope.spawn(move || {
+            let _thread_span = info_span!("scraper_thread", id).entered();
             let client = blocking::Client::new();
             loop {
                 // Tell main which thread needs an article
@@ -395,8 +398,9 @@ impl Scraper {
                 let article: &mut Article = unsafe { article_ptr.get_mut_ptr() };
 
                 let url = String::from(WIKI_PREFIX) + article.url.as_str();
+                let _page_span = info_span!("page", url = %article.url).entered();
 
-                eprintln!("{}Request sent: {}", SCRAPER_HEADING, url);
+                debug!("Request sent");
 
                 let document_text;
                 let mut retries = 0;
@@ -420,7 +424,7 @@ impl Scraper {
                     };
                 }
 
-                eprintln!("{}Response recieved: {}", SCRAPER_HEADING, url);
+                debug!("Response recieved");
 
                 let page_id_captures = PAGE_ID_PATTERN
                     .captures(document_text.as_str())
@@ -450,6 +454,7 @@ impl Scraper {
 
                 article.tags = tags;
                 article.page_id = page_id;
+                info!(page_id, tags = article.tags.len(), "Page scraped");
 
                 let text = match self.make_vote_request(&client, page_id) {
                     Ok(text) => text,
@@ -512,7 +517,7 @@ impl Scraper {
                 }
                 Err(e) => {
                     if retries >= MAX_RETRIES {
-                        eprintln!("{}Multi-retry error: {:?}", SCRAPER_HEADING, e);
+                        error!(error = ?e, "Multi-retry error");
                         panic!();
                     }
                     retries += 1;
@@ -4,24 +4,53 @@ use arrow_array::{
     ArrayRef, Int8Array, RecordBatch, StringArray, UInt64Array,
 };
 use arrow_schema::{DataType, Field, Schema};
-use lotus::OutputFiles;
-use parquet::arrow::ArrowWriter;
-use std::{collections::HashMap, fs::File, io::Error, sync::Arc};
-
-// Message to print before all writer logs
-const WRITER_HEADING: &str = "[WRITER] ";
+use lotus::{OutputFiles, SCRAPED_AT_KEY};
+use parquet::{
+    arrow::ArrowWriter,
+    file::{metadata::KeyValue, properties::WriterProperties},
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Error,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, info_span};
+
+/// Record all the scraped info to parquet files for fast, efficient access.
+/// When the scrape started is saved in the metadata of every file, under `SCRAPED_AT_KEY`.
+pub fn record_info(
+    scraped_info: ScrapeInfo,
+    outputs: OutputFiles,
+    started: SystemTime,
+) -> Result<(), Error> {
+    let _span = info_span!("writer").entered();
+    info!("Starting writing");
+
+    let scraped_at = started
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs());
+    let properties = WriterProperties::builder()
+        .set_key_value_metadata(Some(vec![KeyValue::new(
+            String::from(SCRAPED_AT_KEY),
+            scraped_at.to_string(),
+        )]))
+        .build();
 
-/// Record all the scraped info to parquet files for fast, efficient access
-pub fn record_info(scraped_info: ScrapeInfo, outputs: OutputFiles) -> Result<(), Error> {
-    eprintln!("{}Starting writing", WRITER_HEADING);
     record_articles_votes(
         scraped_info.articles,
         outputs.article_output.as_str(),
         outputs.votes_output.as_str(),
+        &properties,
+    )?;
+    record_users(
+        scraped_info.users,
+        outputs.users_output.as_str(),
+        &properties,
     )?;
-    record_users(scraped_info.users, outputs.users_output.as_str())?;
-    record_tags(scraped_info.tags, outputs.tags_output.as_str())?;
-    eprintln!("{}Writing completed successfully", WRITER_HEADING);
+    record_tags(scraped_info.tags, outputs.tags_output.as_str(), &properties)?;
+    info!("Writing completed successfully");
     Ok(())
 }
 
@@ -29,6 +58,7 @@ fn record_articles_votes(
     articles: Vec<Article>,
     articles_output: &str,
     votes_output: &str,
+    properties: &WriterProperties,
 ) -> Result<(), Error> {
     let mut article_pids = Vec::with_capacity(articles.len());
     let mut names = Vec::with_capacity(articles.len());
@@ -42,7 +72,7 @@ fn record_articles_votes(
     let mut uids = Vec::with_capacity(articles.len());
 
     // Article info must be deconstructed into vectors for both articles and votes
-    eprintln!("{}Deconstructing articles", WRITER_HEADING);
+    info!("Deconstructing articles");
     for article in articles {
         article_pids.push(article.page_id);
         names.push(article.name);
@@ -56,8 +86,15 @@ fn record_articles_votes(
         }
     }
 
-    record_articles(names, urls, article_pids, tag_lists, articles_output)?;
-    record_votes(vote_pids, uids, ratings, votes_output)?;
+    record_articles(
+        names,
+        urls,
+        article_pids,
+        tag_lists,
+        articles_output,
+        properties,
+    )?;
+    record_votes(vote_pids, uids, ratings, votes_output, properties)?;
 
     Ok(())
 }
@@ -68,8 +105,9 @@ fn record_articles(
     pids: Vec<u64>,
     tag_lists: Vec<Vec<u16>>,
     output_name: &str,
+    properties: &WriterProperties,
 ) -> Result<(), Error> {
-    eprintln!("{}Recording articles", WRITER_HEADING);
+    info!("Recording articles");
     let tag_field = Field::new(
         "tags",
         DataType::List(Arc::new(Field::new("item", DataType::UInt16, false))),
@@ -98,25 +136,33 @@ fn record_articles(
         Arc::new(builder.finish()),
     ];
 
-    record_batch(schema, output_name, records)?;
-    eprintln!("{}Articles recorded successfully", WRITER_HEADING);
+    record_batch(schema, output_name, records, properties)?;
+    info!("Articles recorded successfully");
     Ok(())
 }
 
-fn record_tags(tags: Vec<String>, output_name: &str) -> Result<(), Error> {
-    eprintln!("{}Recording tags", WRITER_HEADING);
+fn record_tags(
+    tags: Vec<String>,
+    output_name: &str,
+    properties: &WriterProperties,
+) -> Result<(), Error> {
+    info!("Recording tags");
     let schema = Schema::new(vec![Field::new("tag", DataType::Utf8, false)]);
 
     let records: Vec<ArrayRef> = vec![Arc::new(StringArray::from(tags))];
 
-    record_batch(schema, output_name, records)?;
-    eprintln!("{}Tags recorded successfully", WRITER_HEADING);
+    record_batch(schema, output_name, records, properties)?;
+    info!("Tags recorded successfully");
 
     Ok(())
 }
 
-fn record_users(users: HashMap<u64, User>, output_name: &str) -> Result<(), Error> {
-    eprintln!("{}Recording users", WRITER_HEADING);
+fn record_users(
+    users: HashMap<u64, User>,
+    output_name: &str,
+    properties: &WriterProperties,
+) -> Result<(), Error> {
+    info!("Recording users");
     let schema = Schema::new(vec![
         Field::new("name", DataType::Utf8, false),
         Field::new("url", DataType::Utf8, false),
@@ -139,8 +185,8 @@ fn record_users(users: HashMap<u64, User>, output_name: &str) -> Result<(), Erro
         Arc::new(UInt64Array::from(user_id)),
     ];
 
-    record_batch(schema, output_name, records)?;
-    eprintln!("{}Users recorded successfully", WRITER_HEADING);
+    record_batch(schema, output_name, records, properties)?;
+    info!("Users recorded successfully");
     Ok(())
 }
 
@@ -149,8 +195,9 @@ fn record_votes(
     uids: Vec<u64>,
     ratings: Vec<i8>,
     output_name: &str,
+    properties: &WriterProperties,
 ) -> Result<(), Error> {
-    eprintln!("{}Recording votes", WRITER_HEADING);
+    info!("Recording votes");
     let schema = Schema::new(vec![
         Field::new("pid", DataType::UInt64, false),
         Field::new("uid", DataType::UInt64, false),
@@ -163,18 +210,24 @@ fn record_votes(
         Arc::new(Int8Array::from(ratings)),
     ];
 
-    record_batch(schema, output_name, records)?;
-    eprintln!("{}Votes recorded successfully", WRITER_HEADING);
+    record_batch(schema, output_name, records, properties)?;
+    info!("Votes recorded successfully");
     Ok(())
 }
 
 // Records information in a parquet file
-fn record_batch(schema: Schema, file_name: &str, record_vec: Vec<ArrayRef>) -> Result<(), Error> {
+fn record_batch(
+    schema: Schema,
+    file_name: &str,
+    record_vec: Vec<ArrayRef>,
+    properties: &WriterProperties,
+) -> Result<(), Error> {
     let mut buffer = File::create(file_name)?;
     let to_write = RecordBatch::try_new(Arc::new(schema), record_vec)
         .expect("Hardcoded schema should align with other hardcoded schema");
 
-    let mut writer = ArrowWriter::try_new(&mut buffer, to_write.schema(), None)?;
+    let mut writer =
+        ArrowWriter::try_new(&mut buffer, to_write.schema(), Some(properties.clone()))?;
     writer.write(&to_write)?;
     writer.close()?;
 
@@ -184,7 +237,8 @@ fn record_batch(schema: Schema, file_name: &str, record_vec: Vec<ArrayRef>) -> R
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::env;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use std::{env, time::Duration};
 
     #[test]
     fn write_info() {
@@ -199,8 +253,20 @@ mod tests {
             users_output: format!("{}/users.parquet", out_dir),
             votes_output: format!("{}/votes.parquet", out_dir),
         };
-
-        record_info(info, outputs).expect("Test failed");
+        let votes_output = outputs.votes_output.clone();
+
+        let started = UNIX_EPOCH + Duration::from_secs(1_726_099_200);
+        record_info(info, outputs, started).expect("Test failed");
+
+        // The scrape time can be read back from the file
+        let reader = SerializedFileReader::new(File::open(votes_output).unwrap()).unwrap();
+        let scraped_at = reader
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .and_then(|metadata| metadata.iter().find(|pair| pair.key == SCRAPED_AT_KEY))
+            .and_then(|pair| pair.value.clone());
+        assert_eq!(scraped_at.as_deref(), Some("1726099200"));
     }
 
     #[test]
@@ -218,7 +284,7 @@ mod tests {
             votes_output: format!("{}/votes.parquet", out_dir),
         };
 
-        record_info(info, outputs).expect("Test failed");
+        record_info(info, outputs, SystemTime::now()).expect("Test failed");
     }
 
     // Create fake data
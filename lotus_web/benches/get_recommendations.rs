@@ -1,7 +1,7 @@
 use std::hint::black_box;
 
 use const_format::formatcp;
-use lotus_web::recommender::{Recommender, RecommenderOptions};
+use lotus_web::recommender::{Recommender, RecommenderOptions, TagFilter};
 
 // Directory where output files can be found
 const OUTPUT_DIR: &str = "../output";
@@ -27,7 +27,7 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         b.iter(|| {
             recommender.get_recommendations_by_uid(
                 black_box(7904845),
-                black_box(Vec::new()),
+                black_box(&TagFilter::default()),
                 black_box(Vec::new()),
             )
         })
@@ -36,7 +36,7 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         b.iter(|| {
             recommender.get_recommendations_by_uid(
                 black_box(7904845),
-                black_box(vec![734]),
+                black_box(&TagFilter::Tag(734)),
                 black_box(Vec::new()),
             )
         })
@@ -45,7 +45,7 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         b.iter(|| {
             recommender.get_recommendations_by_uid(
                 black_box(7904845),
-                black_box(Vec::new()),
+                black_box(&TagFilter::default()),
                 black_box(vec![2528233]),
             )
         })
@@ -54,7 +54,7 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         b.iter(|| {
             recommender.get_recommendations_by_uid(
                 black_box(7904845),
-                black_box(vec![734]),
+                black_box(&TagFilter::Tag(734)),
                 black_box(vec![25282833]),
             )
         })
@@ -6,18 +6,19 @@ const WIKI_PREFIX = "https://scp-wiki.wikidot.com/";
 
 let acSelected = -1;
 let bans = undefined;
+let diversity = 0;
+let novelty = "";
+// Token of the server-side profile bans are synced with, or "" if there is none
+let profileToken = "";
 let isRequesting = false;
+let nextCursor = null;
 let recs = [];
 let recsPerPage = 30;
+let recsUrl = "";
 let tagContainer = document.getElementById("tag-container");
 let tagStrings = [];
 let userSearchElement = document.getElementById("user-search");
 
-// Compares two usernames on the wiki
-function compareNames(name1, name2) {
-    return name1.localeCompare(name2, undefined, { "caseFirst": "upper" });
-}
-
 // Show/hide the tag popup
 function toggleTagPopup() {
     document.getElementById("tag-search").value = "";
@@ -107,15 +108,70 @@ function createIdBanFunc(id, name) {
         event.target.parentNode.remove();
         bans.push({ pid: id, name: name });
         window.localStorage.setItem("bans", JSON.stringify(bans));
+        if (profileToken !== "") {
+            profileRequest("PUT", `/marks/${id}`, { kind: "ban" });
+        }
 
         recs = recs.filter((value) => {
             return value.pid != id;
         });
 
         displayRecs(recs);
+
+        if (recs.length < recsPerPage) {
+            showMoreRecs();
+        }
+    }
+}
+
+// Fetch the next window of recommendations from the last request and add them to the
+// displayed ones
+async function showMoreRecs() {
+    if (isRequesting || nextCursor === null) {
+        return;
+    }
+
+    isRequesting = true;
+
+    try {
+        const response = await fetch(recsUrl + "&cursor=" + encodeURIComponent(nextCursor), { headers: profileHeaders() });
+        if (!response.ok) {
+            throw new Error(`Response status: ${response.status}`);
+        }
+
+        const json = await response.json();
+
+        nextCursor = json.next_cursor;
+        recs = recs.concat(json.recommendations.filter((page) => {
+            return !bans.some((ban) => ban.pid == page.pid);
+        }));
+
+        displayRecs(recs);
+    } catch (error) {
+        console.error(error.message);
+    } finally {
+        isRequesting = false;
     }
 }
 
+// Describe why a page was recommended, from the explanation the server gave for it
+function describeExplanation(explanation) {
+    let reasons = [];
+
+    if (explanation.top_neighbors.length > 0) {
+        let names = explanation.top_neighbors.map((neighbor) => neighbor.name);
+        reasons.push(`Liked by similar users like ${names.join(", ")}`);
+    }
+
+    reasons.push(`${explanation.neighbor_upvotes} similar users upvoted, ${explanation.neighbor_downvotes} downvoted`);
+
+    if (explanation.shared_tags.length > 0) {
+        reasons.push(`Shares tags with pages you liked: ${explanation.shared_tags.join(", ")}`);
+    }
+
+    return reasons.join("\n");
+}
+
 // Display the given recommendations in the recommendations container
 // Assumes the recommendations container is not hidden in any way
 function displayRecs(recs) {
@@ -137,6 +193,10 @@ function displayRecs(recs) {
         recLink.setAttribute("target", "_blank");
         recLink.classList.add("rec-link");
 
+        if (page.explanation) {
+            recLink.setAttribute("title", describeExplanation(page.explanation));
+        }
+
         recBan.addEventListener("click", createIdBanFunc(page.pid, page.name));
         recBan.classList.add("ban-button");
 
@@ -161,18 +221,19 @@ async function showRecs(tags) {
     isRequesting = true;
 
     let username = document.getElementById("user-search").value;
-    let url = window.location.href + "rec";
+    let url = window.location.href + "api/v1/recommendations";
     url += "?user=" + encodeURIComponent(username);
+    url += "&explain=true";
 
     if (tagStrings.length > 0) {
         url += "&tags=";
+        // Tags are sent by name, since some names (like "1000") are also the ids of other tags
         for (const tag of tagStrings) {
-            let tagId = tags.indexOf(tag).toString();
-            if (tagId == "-1") {
+            if (!tags.includes(tag)) {
                 continue;
             }
 
-            url += tagId;
+            url += encodeURIComponent(tag);
             url += "+";
         }
 
@@ -180,7 +241,8 @@ async function showRecs(tags) {
         url = url.substring(0, url.length - 1);
     }
 
-    if (bans.length > 0) {
+    // Synced bans are added by the server, so the url does not grow with them
+    if (bans.length > 0 && profileToken === "") {
         url += "&bans=";
 
         // Filter out anything which isn't a proper bans object with a pid that is a number
@@ -200,6 +262,16 @@ async function showRecs(tags) {
         url = url.substring(0, url.length - 1);
     }
 
+    if (diversity > 0) {
+        url += "&diversity=" + diversity;
+    }
+
+    if (novelty !== "") {
+        url += "&novelty=" + novelty;
+    }
+
+    url += "&limit=" + recsPerPage;
+
     try {
         document.getElementById("rec-container-container").classList.remove("hidden");
 
@@ -214,44 +286,33 @@ async function showRecs(tags) {
 
         recommendationsContainer.appendChild(loadingElement);
 
-        const response = await fetch(url);
-        if (!response.ok) {
-            console.log(response);
-
-            recommendationsContainer.innerHTML = "";
-
-            let errorElement = document.createElement("div");
-            errorElement.classList.add("response-text");
-            errorElement.classList.add("error");
-            errorElement.innerHTML = `ERROR ${response.status}`;
+        const response = await fetch(url, { headers: profileHeaders() });
 
-            let statusText = document.createElement("p");
-            statusText.classList.add("status-text");
-            statusText.innerHTML = response.statusText;
+        isRequesting = false;
 
-            recommendationsContainer.appendChild(errorElement);
-            recommendationsContainer.appendChild(statusText);
+        recommendationsContainer.innerHTML = "";
 
-            isRequesting = false;
-            throw new Error(`Response status: ${response.status}`);
+        let json = null;
+        try {
+            json = await response.json();
+        } catch {
+            // Errors from outside of the API (like proxies) may not be JSON
         }
 
-        isRequesting = false;
-
-        json = await response.json();
-
-        recommendationsContainer.innerHTML = "";
+        if (!response.ok) {
+            console.log(response);
 
-        if ('type' in json && json.type == "error") {
             let errorElement = document.createElement("div");
             errorElement.classList.add("response-text");
             errorElement.classList.add("error");
+            errorElement.innerHTML = `ERROR ${response.status}`;
 
             let statusText = document.createElement("p");
             statusText.classList.add("status-text");
+            statusText.innerHTML = response.statusText;
 
-            switch (json.code) {
-                case "USER_PARSE_ERROR":
+            switch (json?.code) {
+                case "USER_NOT_FOUND":
                     errorElement.innerHTML = "USER NOT FOUND";
                     statusText.innerHTML = "A user by that name/id could not be found by the recommender. They may not have voted enough times by the latest scrape.";
                     break;
@@ -259,6 +320,35 @@ async function showRecs(tags) {
                     errorElement.innerHTML = "USER NOT SENT";
                     statusText.innerHTML = "Username was not properly sent to the server. Try again soon or create a GitHub issue if this continues";
                     break;
+                case "INVALID_TAGS":
+                case "INVALID_BANS":
+                    errorElement.innerHTML = "BAD FILTERS";
+                    statusText.innerHTML = "The selected tags or banned pages could not be read. Try reselecting them or clearing your bans in the settings.";
+                    break;
+                case "INVALID_DIVERSITY":
+                    errorElement.innerHTML = "BAD DIVERSITY";
+                    statusText.innerHTML = "The diversity setting could not be read. Set it to a number from 0 to 1 in the settings.";
+                    break;
+                case "INVALID_NOVELTY":
+                    errorElement.innerHTML = "BAD NOVELTY";
+                    statusText.innerHTML = "The novelty setting could not be read. Set it to a number from 0 to 1 in the settings, or leave it blank.";
+                    break;
+                case "PROFILE_NOT_FOUND":
+                    errorElement.innerHTML = "SYNC CODE NOT FOUND";
+                    statusText.innerHTML = "Your sync code is not known to the server. Enter a different one or clear it in the settings.";
+                    break;
+                case "INVALID_QUERY":
+                    errorElement.innerHTML = "BAD SETTINGS";
+                    statusText.innerHTML = "Some settings could not be read. Make sure diversity and novelty are numbers in the settings.";
+                    break;
+                case "NOT_READY":
+                    errorElement.innerHTML = "SERVER STARTING";
+                    statusText.innerHTML = "The server is still loading the latest data. Try again in a minute.";
+                    break;
+                case "SERVER_BUSY":
+                    errorElement.innerHTML = "SERVER BUSY";
+                    statusText.innerHTML = "The server is handling too many requests right now. Try again in a few seconds.";
+                    break;
                 case "RECOMMENDER_ERROR":
                     errorElement.innerHTML = "RECOMMENDER ERROR";
                     statusText.innerHTML = "Something went wrong with the recommendation process. Try again soon or create a GitHub issue if this continues.";
@@ -268,10 +358,12 @@ async function showRecs(tags) {
             recommendationsContainer.appendChild(errorElement);
             recommendationsContainer.appendChild(statusText);
 
-            throw new Error(`Valid response with error: ${json.code}`);
+            throw new Error(`Response status: ${response.status} ${json?.code}`);
         }
 
-        recs = json;
+        recs = json.recommendations;
+        recsUrl = url;
+        nextCursor = json.next_cursor;
 
         displayRecs(recs);
     } catch (error) {
@@ -289,6 +381,9 @@ function createIdUnbanFunc(pid) {
         });
 
         window.localStorage.setItem("bans", JSON.stringify(bans));
+        if (profileToken !== "") {
+            profileRequest("DELETE", `/marks/${pid}`);
+        }
     }
 }
 
@@ -317,6 +412,27 @@ function toggleSettings() {
             }
         }
 
+        let newDiversity = Number.parseFloat(document.getElementById("diversity").value);
+        if (!Number.isNaN(newDiversity)) {
+            diversity = Math.min(Math.max(newDiversity, 0), 1);
+            window.localStorage.setItem("diversity", diversity.toString());
+        }
+
+        let newNovelty = document.getElementById("novelty").value.trim();
+        if (newNovelty === "") {
+            novelty = "";
+            window.localStorage.removeItem("novelty");
+        }
+        else if (!Number.isNaN(Number.parseFloat(newNovelty))) {
+            novelty = Math.min(Math.max(Number.parseFloat(newNovelty), 0), 1).toString();
+            window.localStorage.setItem("novelty", novelty);
+        }
+
+        let newToken = document.getElementById("profile-token").value.trim();
+        if (newToken !== profileToken) {
+            linkProfile(newToken);
+        }
+
         settingsContainer.classList.add("hidden");
         return;
     }
@@ -342,6 +458,87 @@ function toggleSettings() {
     }
 
     document.getElementById("rec-count").value = recsPerPage;
+    document.getElementById("diversity").value = diversity;
+    document.getElementById("novelty").value = novelty;
+    document.getElementById("profile-token").value = profileToken;
+    document.getElementById("profile-status").textContent = "";
+}
+
+// Headers which tell the server which profile to use, if bans are synced
+function profileHeaders() {
+    return profileToken === "" ? {} : { "Authorization": `Bearer ${profileToken}` };
+}
+
+// Send a request to change the synced profile, returning the response
+async function profileRequest(method, path, body) {
+    let options = { method: method, headers: profileHeaders() };
+    if (body !== undefined) {
+        options.headers["Content-Type"] = "application/json";
+        options.body = JSON.stringify(body);
+    }
+
+    try {
+        return await fetch("api/v1/profile" + path, options);
+    } catch (error) {
+        console.error(error.message);
+        return null;
+    }
+}
+
+// Replace the stored bans with the ones saved in the synced profile
+async function loadProfile() {
+    const response = await profileRequest("GET", "");
+    if (!response?.ok) {
+        return false;
+    }
+
+    const profile = await response.json();
+    bans = profile.marks.map((mark) => {
+        return { pid: mark.pid, name: mark.name ?? mark.pid.toString() };
+    });
+    window.localStorage.setItem("bans", JSON.stringify(bans));
+
+    return true;
+}
+
+// Start syncing bans with the profile with the given token, or stop syncing if it is empty.
+// Bans made on this device before syncing are added to the profile.
+async function linkProfile(token) {
+    profileToken = token;
+    if (token === "") {
+        window.localStorage.removeItem("profileToken");
+        return;
+    }
+
+    const localBans = bans;
+    if (!await loadProfile()) {
+        profileToken = "";
+        document.getElementById("profile-status").textContent = "That sync code could not be used.";
+        return;
+    }
+    window.localStorage.setItem("profileToken", token);
+
+    for (const ban of localBans) {
+        if (!bans.some((synced) => synced.pid == ban.pid)) {
+            await profileRequest("PUT", `/marks/${ban.pid}`, { kind: "ban" });
+            bans.push(ban);
+        }
+    }
+    window.localStorage.setItem("bans", JSON.stringify(bans));
+}
+
+// Make a new profile and start syncing bans with it
+async function createProfile() {
+    const response = await fetch("api/v1/profile", { method: "POST" });
+    if (!response.ok) {
+        document.getElementById("profile-status").textContent = "This server cannot sync bans.";
+        return;
+    }
+
+    const created = await response.json();
+    await linkProfile(created.token);
+    document.getElementById("profile-token").value = created.token;
+    document.getElementById("profile-status").textContent = "Enter this code on your other devices.";
 }
 
 // Close the user autocomplete popup
@@ -352,17 +549,29 @@ function closeAutocomplete() {
     }
 }
 
-// Set up custom autocomplete for the user-search from the given list of names
-function user_autocomplete(usernames) {
-    return function(event) {
+// Set up autocomplete for the user-search, using the server's user search.
+// The server ignores case, accents, and small typos, so results may not start with the input
+function user_autocomplete() {
+    // Only the newest search is shown, so a slow response cannot replace a newer one
+    let latestSearch = 0;
+
+    return async function(event) {
         let value = event.target.value;
+        let search = ++latestSearch;
 
-        closeAutocomplete();
+        if (!value.trim()) {
+            closeAutocomplete();
+            return false;
+        }
 
-        if (!value) {
+        let response = await fetch(`api/v1/users/search?q=${encodeURIComponent(value)}&limit=${MAX_RESULTS}`);
+        if (search != latestSearch || !response.ok) {
             return false;
         }
 
+        let users = (await response.json()).users;
+
+        closeAutocomplete();
         acSelected = -1;
 
         let resultContainer = document.createElement("div");
@@ -371,31 +580,25 @@ function user_autocomplete(usernames) {
 
         event.target.parentNode.appendChild(resultContainer);
 
-        let ind = binarySearchIndex(value, usernames, 0, usernames.length - 1, compareNames);
-
-        for (let i = ind; i < ind + MAX_RESULTS; i++) {
-            let shared_substr = usernames[i].substr(0, value.length);
-
-            // Do not overflow into users not starting with shared_substr
-            if (shared_substr.toLowerCase() != value.toLowerCase()) {
-                break;
-            }
-
+        for (let user of users) {
             let result = document.createElement("div");
 
+            // Highlight what was typed, if the name starts with it
+            let sharedLength = user.name.toLowerCase().startsWith(value.toLowerCase()) ? value.length : 0;
+
             let shared = document.createElement("span");
             shared.classList.add("user-ac-shared")
-            shared.innerHTML = shared_substr;
+            shared.textContent = user.name.substr(0, sharedLength);
 
             let other = document.createElement("span");
             other.classList.add("user-ac-other")
-            other.innerHTML = usernames[i].substr(value.length);
+            other.textContent = user.name.substr(sharedLength);
 
             result.appendChild(shared);
             result.appendChild(other);
 
             result.addEventListener("click", () => {
-                userSearchElement.value = usernames[i];
+                userSearchElement.value = user.name;
                 closeAutocomplete();
             });
 
@@ -456,13 +659,9 @@ function autocompleteHandlePresses(event) {
 // Run code to get everything in working order after the page has been loaded.
 // Loads stored info and adds event listeners
 async function setUpPage() {
-    const usernameResponse = await fetch("files/usernames.json");
-    const usernames = await usernameResponse.json();
-    const tagsResponse = await fetch("files/tags.json");
+    const tagsResponse = await fetch("api/v1/tags");
     const tags = await tagsResponse.json();
 
-    usernames.sort(compareNames);
-
     // Put a placeholder value in if there are no existing bans
     if (!localStorage.getItem("bans")) {
         window.localStorage.setItem("bans", "[]");
@@ -476,6 +675,19 @@ async function setUpPage() {
 
     recsPerPage = Number.parseInt(window.localStorage.getItem("recsPerPage"));
 
+    if (!localStorage.getItem("diversity")) {
+        window.localStorage.setItem("diversity", "0");
+    }
+
+    diversity = Number.parseFloat(window.localStorage.getItem("diversity"));
+
+    novelty = window.localStorage.getItem("novelty") ?? "";
+
+    profileToken = window.localStorage.getItem("profileToken") ?? "";
+    if (profileToken !== "") {
+        await loadProfile();
+    }
+
     document.getElementById("tag-select-button").addEventListener('click', toggleTagPopup);
 
     document.getElementById("tag-search").addEventListener("input", (event) => {
@@ -503,6 +715,7 @@ async function setUpPage() {
     }
 
     document.getElementById("settings-close").addEventListener("click", toggleSettings);
+    document.getElementById("profile-create").addEventListener("click", createProfile);
 
     document.getElementById("search-button").addEventListener('click', () => {
         showRecs(tags);
@@ -516,7 +729,7 @@ async function setUpPage() {
         }
     });
 
-    userSearchElement.addEventListener("input", user_autocomplete(usernames));
+    userSearchElement.addEventListener("input", user_autocomplete());
     userSearchElement.addEventListener("keydown", autocompleteHandlePresses);
 
     document.addEventListener("click", (event) => {
@@ -1,5 +1,2 @@
 pub mod server;
 pub mod recommender;
-
-/// Message to print before all server logs
-pub const SERVER_HEADING: &str = "[SERVER] ";
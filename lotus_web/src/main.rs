@@ -1,127 +1,262 @@
-use axum::{routing::get, Router};
+use axum::{
+    extract::Request,
+    middleware,
+    routing::{get, post, put},
+    Router,
+};
+use lotus::{init_logging, WebConfig};
 use lotus_web::{
     recommender::{Recommender, RecommenderOptions},
-    server, SERVER_HEADING,
+    server::{
+        self, AppState, Metrics, ProfileStore, RankingCache, ServerInfo, ServingOptions, WorkPool,
+    },
+};
+use std::{
+    net::TcpListener,
+    path::Path,
+    process,
+    sync::{Arc, OnceLock},
+    time::Duration,
 };
-use std::io::prelude::*;
-use std::{env, fs::File, process, sync::Arc};
-use tower_http::services::ServeDir;
+use tokio::{runtime, task};
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, RequestId, SetRequestIdLayer},
+    services::ServeDir,
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use tracing::{error, info, info_span, warn, Level};
 
-#[tokio::main]
-async fn main() {
-    let args: Vec<String> = env::args().collect();
+fn main() {
+    // Taking the socket changes the environment, which is only safe before any threads start
+    let activated = server::take_activated_listener();
 
-    let mut options = RecommenderOptions::new();
+    runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Async runtime should always be creatable")
+        .block_on(run(activated));
+}
 
-    let mut index = 1;
-    let length = args.len();
+async fn run(activated: Option<TcpListener>) {
+    let config = match WebConfig::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
 
-    let mut ip = "0.0.0.0:3000";
+    if let Err(e) = init_logging(config.log.level.as_deref(), config.log.json) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
 
-    while index < length {
-        options = match args[index].as_str() {
-            "--article-file" | "-a" => {
-                let articles_file = args.get(index + 1).expect("No article file specified");
-                index += 1;
-                options.with_articles_file(articles_file.clone())
-            }
-            "--tags-file" | "-t" => {
-                let tags_file = args.get(index + 1).expect("No tags file specified");
-                index += 1;
-                options.with_tags_file(tags_file.clone())
-            }
-            "--users-file" | "-u" => {
-                let users_file = args.get(index + 1).expect("No users file specified");
-                index += 1;
-                options.with_users_file(users_file.clone())
-            }
-            "--votes-file" | "-v" => {
-                let votes_file = args.get(index + 1).expect("No votes file specified");
-                index += 1;
-                options.with_votes_file(votes_file.clone())
-            }
-            "--address" | "-i" => {
-                ip = args.get(index + 1).expect("No users to consider specified");
-                index += 1;
-                options
-            }
-            "--min-votes" | "-m" => {
-                let min_votes = args
-                    .get(index + 1)
-                    .expect("No minimum votes specified")
-                    .parse()
-                    .expect("Wrong format of min-votes. Must be a 16 bit unsigned integer.");
-                index += 1;
-                options.with_min_votes(min_votes)
-            }
-            "--users-to-consider" | "-c" => {
-                let users_to_consider = args
-                    .get(index + 1)
-                    .expect("No users to consider specified")
-                    .parse()
-                    .expect(
-                        "Wrong format of users-to-consider. Must be a 32 bit unsigned integer.",
-                    );
-                index += 1;
-                options.with_users_to_consider(users_to_consider)
-            }
-            "--help" | "-h" => {
-                println!("Usage: lotus_web [args]\n  If an arg is passed multiple times, only the rightmost is considered.\n\n  Output file arguments:           Specify the save location of different data.\n    --article-file        or -a    Default: .outputarticles.parquet\n    --tags-file           or -t    Default: .outputtags.parquet\n    --users-file          or -u    Default: .outputusers.parquet\n    --votes-file          or -v    Default: .outputvotes.parquet\n\n  Other options:\n    Sets the ip address to listen for connections on, with the port specified.\n    See the default for formatting example.\n    --address           or -i    Default: 0.0.0.0:3000\n\n    Sets the minimum number of votes each user must have to be included in the recommender.\n    Setting this too low slows recommendation speed and uses a lot of memory.\n    However, any users with less than this many votes will not be considered for recommendations.\n    --min-votes         or -m    Default: 10\n\n    Sets the number of similar users to consider for each recommendation.\n    Setting this too high leads to more popularity bias and slightly slower recommendations.\n    However, it also takes more user opinions into account, which potentially gives varied recommendations.\n    --users-to-consider or -c    Default: 0\n\n    Display this message instead of running the system.\n    --help              or -h");
-                return;
-            }
-            other => {
-                println!(
-                    "Unknown command line option: {}.\nRun with --help (or -h) for valid commands.",
-                    other
-                );
+    let options = RecommenderOptions::new()
+        .with_outputs(config.outputs.clone())
+        .with_min_votes(config.min_votes)
+        .with_users_to_consider(config.users_to_consider)
+        .with_novelty(config.novelty)
+        .with_upvote_weight(config.upvote_weight)
+        .with_downvote_weight(config.downvote_weight)
+        .with_downvote_exclusion(config.exclude_downvoted);
+
+    let snapshot_file = config.snapshot.as_ref();
+
+    if let Some(build_snapshot_file) = &config.build_snapshot {
+        let recommender = load_recommender(snapshot_file.map(String::as_str), &options);
+
+        match recommender.save_snapshot(build_snapshot_file, &options) {
+            Ok(()) => info!(build_snapshot_file, "Snapshot saved"),
+            Err(e) => {
+                error!(error = ?e, "Snapshot failed");
                 process::exit(1);
             }
-        };
+        }
 
-        index += 1;
+        return;
     }
 
-    let recommender = match Recommender::new_with_options(&options) {
-        Ok(rec) => rec,
-        Err(e) => {
-            eprintln!(
-                "{}Recommender startup failed with error: {:?}",
-                SERVER_HEADING, e
-            );
-            process::exit(1);
-        }
-    };
+    info!("Starting web server");
 
-    eprintln!("{}Starting web server...", SERVER_HEADING);
+    if !Path::new(&config.static_dir).is_dir() {
+        warn!(
+            static_dir = config.static_dir,
+            "Static file directory not found, so the site will not work"
+        );
+    }
+    let serve_dir = ServeDir::new(&config.static_dir);
 
-    // Write some things to json files in the files folder
-    let tags = recommender.get_tags();
-    let tags = serde_json::to_string(&tags).expect("Tags should always be serializable");
-    let mut tags_file = File::create("lotus_web/files/tags.json").unwrap();
-    write!(tags_file, "{}", tags).unwrap();
+    let metrics = Metrics::new().expect("Metrics should always be creatable");
 
-    let usernames = recommender.get_users_list();
-    let usernames =
-        serde_json::to_string(&usernames).expect("Usernames should always be serializable");
-    let mut usernames_file = File::create("lotus_web/files/usernames.json").unwrap();
-    write!(usernames_file, "{}", usernames).unwrap();
+    let info = ServerInfo {
+        options: ServingOptions {
+            min_votes: options.get_min_votes(),
+            users_to_consider: options.get_users_to_consider(),
+            novelty: options.get_novelty(),
+            upvote_weight: options.get_upvote_weight(),
+            downvote_weight: options.get_downvote_weight(),
+            exclude_downvoted: options.get_downvote_exclusion(),
+            snapshot: snapshot_file.is_some(),
+            cache_size: config.cache_size,
+            max_concurrent: config.max_concurrent,
+            queue_timeout: config.queue_timeout,
+        },
+    };
+
+    let profiles =
+        config
+            .profiles_db
+            .as_ref()
+            .map(|profiles_db| match ProfileStore::open(profiles_db) {
+                Ok(store) => {
+                    info!(profiles_db, "Profiles turned on");
+                    Arc::new(store)
+                }
+                Err(e) => {
+                    error!(error = ?e, profiles_db, "Profile database could not be opened");
+                    process::exit(1);
+                }
+            });
 
-    let serve_dir = ServeDir::new("lotus_web/files");
+    let state = AppState {
+        recommender: Arc::new(OnceLock::new()),
+        cache: Arc::new(RankingCache::new(config.cache_size)),
+        pool: Arc::new(WorkPool::new(
+            config.max_concurrent,
+            Duration::from_secs(config.queue_timeout),
+        )),
+        metrics: Arc::new(metrics),
+        info: Arc::new(info),
+        lists: Arc::new(OnceLock::new()),
+        profiles,
+    };
 
-    let state = Arc::new(recommender);
+    let api = Router::new()
+        .route("/recommendations", get(server::get_rec))
+        .route("/recommendations/group", get(server::get_group_rec))
+        .route("/cache", get(server::get_cache_stats))
+        .route("/tags", get(server::get_tags))
+        .route("/users", get(server::get_users))
+        .route("/users/search", get(server::search_users))
+        .route("/users/:user", get(server::get_user_taste))
+        .route("/compare", get(server::get_comparison))
+        .route("/pages/search", get(server::search_pages))
+        .route(
+            "/profile",
+            post(server::create_profile)
+                .get(server::get_profile)
+                .delete(server::delete_profile),
+        )
+        .route("/profile/tags", put(server::set_profile_tags))
+        .route(
+            "/profile/marks/:pid",
+            put(server::set_profile_mark).delete(server::remove_profile_mark),
+        );
 
     let app = Router::new()
         .route("/", get(server::root))
         .route("/rec", get(server::get_rec))
+        .route("/rec/group", get(server::get_group_rec))
+        .route("/recommendations", get(server::recommendations_page))
+        .route("/user/:user", get(server::user_page))
+        .route("/compare", get(server::compare_page))
+        .nest("/api/v1", api.clone())
+        // Unversioned routes always point to the latest version of the API
+        .nest("/api", api)
         .nest_service("/files", serve_dir)
-        .with_state(state);
+        .route("/metrics", get(server::get_metrics))
+        .route("/healthz", get(server::get_health))
+        .route("/readyz", get(server::get_ready))
+        .route("/info", get(server::get_info))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            server::track_requests,
+        ))
+        // Layers run from the bottom up, so the id is set before the span is made, and copied
+        // onto the response after
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(make_request_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .with_state(state.clone());
 
-    let listener = tokio::net::TcpListener::bind(ip)
-        .await
+    let snapshot_file = snapshot_file.cloned();
+    let loading = task::spawn_blocking(move || {
+        let recommender = load_recommender(snapshot_file.as_deref(), &options);
+
+        match recommender.get_model_size() {
+            Ok(size) => state.metrics.set_model_size(size),
+            Err(e) => warn!(error = ?e, "Could not get model size"),
+        }
+        match recommender.get_scraped_at() {
+            Some(scraped_at) => state.metrics.set_scraped_at(scraped_at),
+            None => warn!("The data does not say when it was scraped"),
+        }
+
+        if state.recommender.set(recommender).is_err() {
+            unreachable!("The recommender is only loaded once");
+        }
+        info!("Recommender loaded, now serving recommendations");
+    });
+
+    // A server sharing its port would take connections from the old server before it could
+    // answer them, so it only listens once loaded. Otherwise it listens straight away, so that
+    // health checks can see the server is starting rather than dead
+    if config.reuse_port {
+        loading.await.expect("Recommender loading should not panic");
+    }
+
+    let listener = server::get_listener(&config.address, config.reuse_port, activated)
         .expect("Error starting listener");
-    eprintln!("{}Web server listening on port: {}", SERVER_HEADING, ip);
+    info!(
+        address = %listener.local_addr().expect("Listener should have an address"),
+        "Web server listening"
+    );
 
+    // Requests in progress when a shutdown signal arrives are finished before exiting
     axum::serve(listener, app)
+        .with_graceful_shutdown(server::shutdown_signal())
         .await
         .expect("Error while serving pages");
+    info!("Web server stopped");
+}
+
+// Build the recommender from a snapshot if one is given, or from the output files otherwise.
+// The server cannot do anything useful without it, so this exits on failure.
+fn load_recommender(snapshot_file: Option<&str>, options: &RecommenderOptions) -> Recommender {
+    let recommender = match snapshot_file {
+        Some(snapshot_file) => {
+            info!(snapshot_file, "Loading snapshot");
+            Recommender::new_from_snapshot(snapshot_file, options)
+        }
+        None => Recommender::new_with_options(options),
+    };
+
+    match recommender {
+        Ok(recommender) => recommender,
+        Err(e) => {
+            error!(error = ?e, "Recommender startup failed");
+            process::exit(1);
+        }
+    }
+}
+
+// Make the span every log from a request is recorded in.
+// The query is left out, since it can hold usernames.
+fn make_request_span(request: &Request) -> tracing::Span {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .unwrap_or_default();
+
+    info_span!(
+        "request",
+        method = %request.method(),
+        path = request.uri().path(),
+        request_id,
+    )
 }
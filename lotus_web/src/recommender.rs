@@ -1,40 +1,91 @@
+mod compare;
+mod explanation;
+mod group;
+mod page_search;
 mod recommender_types;
+mod snapshot;
+mod tag_filter;
+mod taste;
+mod user_search;
 
+use lotus::SCRAPED_AT_KEY;
 use pivot;
 use polars::{
     datatypes::{PlHashMap, PlHashSet},
     prelude::*,
 };
 use polars_core::utils::Container;
-use polars_lazy::{dsl::col, prelude::*};
-use std::fs::File;
-
+use polars_lazy::{
+    dsl::{col, sum_horizontal},
+    prelude::*,
+};
+use roaring::RoaringBitmap;
+use std::{cmp::Ordering, fs::File, time::Instant};
+use tracing::{debug, info, instrument};
+
+pub use recommender_types::Comparison;
+pub use recommender_types::Explanation;
+pub use recommender_types::GroupStrategy;
+pub use recommender_types::LocalRating;
+pub use recommender_types::ModelSize;
+pub use recommender_types::NeighborContribution;
+pub use recommender_types::Ranking;
 pub use recommender_types::RecommenderError;
 pub use recommender_types::RecommenderOptions;
+pub use recommender_types::TagAffinity;
+pub use recommender_types::TasteProfile;
+pub use tag_filter::TagFilter;
+pub use user_search::UserMatch;
+
+use page_search::PageIndex;
+use tag_filter::TagIndex;
+use user_search::UserIndex;
 
 /// Represents the recommendation system
 pub struct Recommender {
     /// Holds the values of a non-vote in the normalized rating frame, mapping String versions of
     /// the uid to the floating point value
     middle_norms: PlHashMap<String, f64>,
-    /// Holds pages and the info about them (name, pid, tags)
+    /// Holds pages and the info about them (name, url, pid, tags, votes)
     page_frame: DataFrame,
     /// Maps page ids to index in the page frame
     page_map: PlHashMap<u64, usize>,
+    /// Searchable index of the pages in the page frame
+    page_index: PageIndex,
     /// Holds uids (column names) against pids (first column) with the intersection being the
     /// rating given by a user to a page
     rating_frame: LazyFrame,
     /// Holds the list of tags, implicitly against tag id (index)
     tags_frame: DataFrame,
+    /// Pages with each tag, for filtering
+    tag_index: TagIndex,
     /// Holds users and the info about them (name, url, uid)
     /// Sorted by name, ascending
     user_frame: DataFrame,
+    /// Searchable index of the users in the user frame
+    user_index: UserIndex,
     /// The number of similar users' ratings to account for when making a recommendation
     users_to_consider: u32,
+    /// How much to discount pages by their popularity when no other novelty is requested
+    novelty: f64,
+    /// Values of upvotes and downvotes before normalization, used when adding local ratings
+    upvote_weight: f64,
+    downvote_weight: f64,
+    /// Whether to remove pages which most of the similar users who voted on them downvoted
+    downvote_exclusion: bool,
+    /// Hash of the options which change rankings, from `RecommenderOptions::get_engine_hash`
+    engine_hash: u64,
+    /// Unix time the scrape the data came from started, if the scraper recorded it
+    scraped_at: Option<u64>,
 }
 
-/// Heading to put before log messages from the recommender
-static RECOMENDER_HEADING: &str = "[RECOMMENDER] ";
+/// Number of top recommendations which are re-ranked when diversifying.
+/// Everything past this keeps its original order, since it is rarely looked at.
+const DIVERSITY_WINDOW: usize = 100;
+
+/// Distance from a user's non-vote value at which a normalized rating counts as a vote.
+/// This does not need to be that small since the difference between votes is large
+const VOTE_UNCERTAINTY: f64 = 1e-6;
 
 impl Recommender {
     /// Creates a new recommender.
@@ -44,11 +95,13 @@ impl Recommender {
         Self::new_with_options(&RecommenderOptions::new())
     }
 
+    #[instrument(skip_all)]
     pub fn new_with_options(options: &RecommenderOptions) -> Result<Recommender, RecommenderError> {
-        let page_frame = set_up_page_frame(options.get_articles_file())?;
         let user_frame = set_up_user_frame(options.get_users_file())?;
         let rating_frame = set_up_rating_frame(options.get_votes_file())?;
+        let page_frame = set_up_page_frame(options.get_articles_file(), &rating_frame)?;
         let tags_frame = set_up_tags_frame(options.get_tags_file())?;
+        let scraped_at = read_scraped_at(options.get_votes_file())?;
 
         // Create a map of page ids to indicies here
         // This means that the page_frame ordering should NEVER be changed without also changing this map
@@ -69,10 +122,14 @@ impl Recommender {
         }
 
         // i8 values are used for minimizing memory, f64 must be used for recommendations
-        let mut casts = PlHashMap::new();
-        casts.insert("rating", DataType::Float64);
-        let rating_frame = rating_frame.cast(casts, true);
-        eprintln!("{}Frames made", RECOMENDER_HEADING);
+        // This is also where upvotes and downvotes are given their weights
+        let rating_frame = rating_frame.with_column(
+            when(col("rating").gt(lit(0i8)))
+                .then(lit(options.get_upvote_weight()))
+                .otherwise(lit(-options.get_downvote_weight()))
+                .alias("rating"),
+        );
+        info!("Frames made");
 
         // Count votes from each user
         let selected_users = rating_frame
@@ -92,7 +149,7 @@ impl Recommender {
             .unwrap();
 
         let rating_frame = rating_frame.filter(irrel_ignore);
-        eprintln!("{}Irrelevant users discarded", RECOMENDER_HEADING);
+        info!(users = user_frame.height(), "Irrelevant users discarded");
 
         let mut casts = PlHashMap::new();
         casts.insert("pid", DataType::UInt64);
@@ -110,20 +167,29 @@ impl Recommender {
         .fill_null(0f64)
         .cast(casts, false);
 
-        eprintln!("{}Pivoted", RECOMENDER_HEADING);
+        info!("Pivoted");
 
         let mut recommender = Recommender {
             middle_norms: PlHashMap::new(),
             rating_frame,
-            page_frame,
             page_map,
+            page_index: PageIndex::new(&page_frame, &tags_frame)?,
+            tag_index: TagIndex::new(&page_frame, &tags_frame)?,
+            page_frame,
             tags_frame,
+            user_index: UserIndex::new(&user_frame)?,
             user_frame,
             users_to_consider: options.get_users_to_consider(),
+            novelty: options.get_novelty(),
+            upvote_weight: options.get_upvote_weight(),
+            downvote_weight: options.get_downvote_weight(),
+            downvote_exclusion: options.get_downvote_exclusion(),
+            engine_hash: options.get_engine_hash(),
+            scraped_at,
         };
 
         recommender.normalize_rating_frame()?;
-        eprintln!("{}Normalized", RECOMENDER_HEADING);
+        info!("Normalized");
 
         Ok(recommender)
     }
@@ -152,14 +218,15 @@ impl Recommender {
         // Multithreaded unsafe in-place normalization may be faster, but was deemed unneeded due
         // to the speed of the code.
 
-        let first = match frame.get(0) {
+        // The row of non-votes was stacked onto the end
+        let non_votes = match frame.get(frame.height() - 1) {
             Some(row) => row,
             None => unreachable!(),
         };
 
         for (i, name) in frame.get_column_names().iter().skip(1).enumerate() {
             // ndex at i + 1 due to the .skip(1)
-            let value = match first[i + 1] {
+            let value = match non_votes[i + 1] {
                 AnyValue::Float64(val) => val,
                 _ => unreachable!(),
             };
@@ -175,15 +242,16 @@ impl Recommender {
     }
 
     /// Returns the Series representing the given user using the page dataframe
+    #[instrument(level = "debug", skip(self))]
     pub fn get_user_by_username(&self, username: &str) -> Result<Vec<AnyValue>, RecommenderError> {
-        eprintln!("{}Searching for user: {}", RECOMENDER_HEADING, username);
         let names = self.user_frame.column("name")?;
 
         let mut index;
         let mut low = 0;
-        let mut high = names.len() - 1;
+        // Exclusive, so that searching before the first name cannot underflow
+        let mut high = names.len();
 
-        while low <= high {
+        while low < high {
             index = low + (high - low) / 2;
 
             let extracted = match names.get(index)? {
@@ -191,22 +259,34 @@ impl Recommender {
                 _ => unreachable!(),
             };
 
-            if extracted == username {
-                eprintln!("{}User found at position {}", RECOMENDER_HEADING, index);
-                return match self.user_frame.get(index) {
-                    Some(value) => Ok(value),
-                    None => unreachable!(),
-                };
-            } else if extracted > username {
-                high = index - 1;
-            } else {
-                low = index + 1;
+            match extracted.cmp(username) {
+                Ordering::Equal => {
+                    debug!(index, "User found");
+                    return match self.user_frame.get(index) {
+                        Some(value) => Ok(value),
+                        None => unreachable!(),
+                    };
+                }
+                Ordering::Greater => high = index,
+                Ordering::Less => low = index + 1,
             }
         }
 
-        eprintln!("{}User not found", RECOMENDER_HEADING);
+        debug!("User not found");
+
+        Err(RecommenderError::UserNotFound)
+    }
 
-        Err(RecommenderError::Bounds)
+    /// Returns the users best matching a search, best first.
+    /// Both names and url slugs are searched, ignoring case, accents, and small typos.
+    pub fn search_users(&self, query: &str, limit: usize) -> Vec<UserMatch> {
+        self.user_index.search(query, limit)
+    }
+
+    /// Returns the pids of the pages best matching a search, best first.
+    /// Page names, urls, and tags are searched, and the last word may be unfinished.
+    pub fn search_pages(&self, query: &str, limit: usize) -> Vec<u64> {
+        self.page_index.search(query, limit)
     }
 
     /// Returns the Series representing the given page using the page dataframe
@@ -216,20 +296,87 @@ impl Recommender {
                 .page_frame
                 .get(*index)
                 .expect("Recorded index should always be in bounds")),
-            None => Err(RecommenderError::Bounds),
+            None => Err(RecommenderError::PageNotFound),
         }
     }
 
-    /// Return every page ordered by how highly they are recommended
+    /// Return every page ordered by how highly they are recommended.
+    /// Pages without the given tags are removed before weighting, so that they are never scored.
     pub fn get_recommendations_by_uid(
         &self,
         uid: u64,
-        required_tags: Vec<u16>,
+        tags: &TagFilter,
         external_bans: Vec<u64>,
     ) -> Result<LazyFrame, RecommenderError> {
-        let similarity_selector = self.get_similarity_selector(uid)?;
+        let ranking = self.get_ranking_for_tags(uid, &[], tags)?;
+
+        self.filter_ranking(&ranking, &TagFilter::default(), external_bans)
+    }
+
+    /// Rank every page the given user has not voted on, without any tag filters or bans.
+    /// This is the expensive part of making recommendations, so the result can be kept and
+    /// filtered any number of times with `filter_ranking`.
+    #[instrument(skip(self))]
+    pub fn get_ranking_by_uid(&self, uid: u64) -> Result<Ranking, RecommenderError> {
+        self.rank_pages(uid, &[], None)
+    }
 
-        let page_weights = self.rating_frame.clone().select(similarity_selector);
+    /// Rank pages like `get_ranking_by_uid`, with ratings the user gave in LOTUS added to their
+    /// wiki votes before finding similar users. Rated pages are never ranked, like voted ones.
+    #[instrument(skip(self, ratings), fields(ratings = ratings.len()))]
+    pub fn get_ranking_with_ratings(
+        &self,
+        uid: u64,
+        ratings: &[LocalRating],
+    ) -> Result<Ranking, RecommenderError> {
+        self.rank_pages(uid, ratings, None)
+    }
+
+    /// Rank pages like `get_ranking_with_ratings`, but only the pages passing the given tag
+    /// filter. The rest are removed before weighting, which is much faster for narrow filters.
+    /// The ranking can still be filtered further with `filter_ranking`.
+    #[instrument(skip(self, ratings), fields(ratings = ratings.len()))]
+    pub fn get_ranking_for_tags(
+        &self,
+        uid: u64,
+        ratings: &[LocalRating],
+        tags: &TagFilter,
+    ) -> Result<Ranking, RecommenderError> {
+        if tags.is_empty() {
+            return self.rank_pages(uid, ratings, None);
+        }
+
+        let pages = self.tag_index.get_matching_pages(tags);
+        self.rank_pages(uid, ratings, Some(&pages))
+    }
+
+    // Rank the pages the given user has not voted on or rated.
+    // If only some pages are wanted, given as rows of the page frame, the rest are removed first.
+    fn rank_pages(
+        &self,
+        uid: u64,
+        ratings: &[LocalRating],
+        pages: Option<&RoaringBitmap>,
+    ) -> Result<Ranking, RecommenderError> {
+        let start = Instant::now();
+        let (user_vector, uid_unvote) = self.get_user_vector(uid, ratings)?;
+        let neighbors = self.get_neighbors_of(uid, &user_vector)?;
+        let similarity_time = start.elapsed();
+
+        let start = Instant::now();
+        let similarity_selector = self.get_similarity_selector(&neighbors)?;
+
+        // Neighbors are found from every page, but only the wanted pages are weighted
+        let page_filter = match pages {
+            Some(pages) => Some(col("pid").is_in(lit(self.get_pids(pages)?))),
+            None => None,
+        };
+        let rating_frame = match &page_filter {
+            Some(page_filter) => self.rating_frame.clone().filter(page_filter.clone()),
+            None => self.rating_frame.clone(),
+        };
+
+        let page_weights = rating_frame.clone().select(similarity_selector);
 
         // Sum all columns together
         let mut page_weights = match page_weights
@@ -245,12 +392,8 @@ impl Recommender {
         // Create series made of all read pages
         let uid_str = format!("{}", uid);
         let uid_col = col(uid_str.as_str());
-        // This does not need to be that small since the difference between votes is large
-        let f_uncert = lit(1e-6f64);
-        let uid_unvote = lit(match self.middle_norms.get(&format!("{}", uid)) {
-            Some(value) => value.to_owned(),
-            None => return Err(RecommenderError::Bounds),
-        });
+        let f_uncert = lit(VOTE_UNCERTAINTY);
+        let uid_unvote = lit(uid_unvote);
 
         // Filter column
         let ignored_pages = uid_col
@@ -258,78 +401,324 @@ impl Recommender {
             // Check if the user has upvoted already
             .gt(uid_unvote.clone() + f_uncert.clone())
             // Check if the user has downvoted already
-            .or(uid_col.clone().lt(uid_unvote.clone() - f_uncert.clone()))
-            // Check if this has been externally banned
-            .or(col("pid").is_in(lit(Series::from_vec("ext_bans", external_bans))));
+            .or(uid_col.clone().lt(uid_unvote.clone() - f_uncert.clone()));
 
-        let mut recommendations = self
-            .rating_frame
-            .clone()
-            .select([col("pid"), uid_col.clone()])
-            .collect()?;
+        // The user's vector is in the order of the whole rating frame, so it is filtered the same
+        let mut recommendations = self.rating_frame.clone().select([col("pid")]).collect()?;
+        recommendations.with_column(user_vector.with_name(&uid_str))?;
+        if let Some(page_filter) = page_filter {
+            recommendations = recommendations.lazy().filter(page_filter).collect()?;
+        }
 
         recommendations.insert_column(2, page_weights)?;
 
-        if !required_tags.is_empty() {
-            recommendations = self.filter_by_tags(&mut recommendations, required_tags)?
-        }
+        let ignored_pages = if self.downvote_exclusion {
+            recommendations
+                .insert_column(3, self.get_neighbor_disliked(&rating_frame, &neighbors)?)?;
+            ignored_pages.or(col("disliked"))
+        } else {
+            ignored_pages
+        };
+
+        let candidates = recommendations
+            .lazy()
+            .filter(ignored_pages.not())
+            .select([col("*").exclude(["disliked"])])
+            .sort(
+                ["weights"],
+                SortMultipleOptions::new().with_order_descending(true),
+            )
+            .collect()?;
+
+        let weighting_time = start.elapsed();
+        debug!(
+            candidates = candidates.height(),
+            ?similarity_time,
+            ?weighting_time,
+            "Ranked pages"
+        );
+
+        Ok(Ranking {
+            uid,
+            neighbors,
+            candidates,
+            similarity_time,
+            weighting_time,
+        })
+    }
+
+    /// Apply a tag filter and bans to a ranking, keeping it in order
+    #[instrument(skip(self, ranking, external_bans), fields(uid = ranking.uid, bans = external_bans.len()))]
+    pub fn filter_ranking(
+        &self,
+        ranking: &Ranking,
+        tags: &TagFilter,
+        external_bans: Vec<u64>,
+    ) -> Result<LazyFrame, RecommenderError> {
+        self.filter_recommendations(&ranking.candidates, tags, external_bans)
+    }
 
-        let recommendations = recommendations.lazy().filter(ignored_pages.not());
+    /// Apply a tag filter and bans to any set of recommendations with a pid column, like those
+    /// of a group, keeping them in order
+    pub fn filter_recommendations(
+        &self,
+        recommendations: &DataFrame,
+        tags: &TagFilter,
+        external_bans: Vec<u64>,
+    ) -> Result<LazyFrame, RecommenderError> {
+        let mut recommendations = recommendations.clone();
 
-        Ok(recommendations.sort(
-            ["weights"],
-            SortMultipleOptions::new().with_order_descending(true),
+        if !tags.is_empty() {
+            recommendations = self.filter_by_tags(&recommendations, tags)?
+        }
+
+        // Filtering keeps the order, so there is no need to sort again
+        Ok(recommendations.lazy().filter(
+            col("pid")
+                .is_in(lit(Series::from_vec("ext_bans", external_bans)))
+                .not(),
         ))
     }
 
-    // Filter out any articles from a set of recommendations that do not have one of the listed
-    // tags.
+    /// Parse a tag filter expression, like `scp AND (horror OR cosmic) AND NOT joke`.
+    /// Tags can be given by name or id. Tags next to each other must all match.
+    pub fn parse_tag_filter(&self, expression: &str) -> Result<TagFilter, RecommenderError> {
+        self.tag_index.parse(expression)
+    }
+
+    /// Get the id of a tag from either its name or its id
+    pub fn get_tag_id(&self, tag: &str) -> Option<u16> {
+        self.tag_index.get_id(tag)
+    }
+
+    // Remove any articles from a set of recommendations which do not pass a tag filter
     fn filter_by_tags(
         &self,
-        recommendations: &mut DataFrame,
-        required_tags: Vec<u16>,
+        recommendations: &DataFrame,
+        tags: &TagFilter,
     ) -> Result<DataFrame, RecommenderError> {
-        let req_tag_set: PlHashSet<_> = required_tags.into_iter().collect();
+        let matching_pages = self.tag_index.get_matching_pages(tags);
 
-        // Create a Boolean Series which contains tag bans
-        let mask: Series = recommendations
+        let mask: BooleanChunked = recommendations
             .column("pid")
             .expect("pid column should always exist")
+            .u64()?
+            .into_no_null_iter()
+            .map(|pid| {
+                let row = self.page_map.get(&pid).expect("page should always exist");
+                matching_pages.contains(*row as u32)
+            })
+            .collect();
+
+        Ok(recommendations.filter(&mask)?)
+    }
+
+    /// Discount the weights of a set of recommendations by how popular each page is, then sort
+    /// them again.
+    /// Novelty is the exponent applied to each page's vote count, so 0 changes nothing and 1
+    /// divides each weight by the number of votes on its page.
+    pub fn discount_popularity(
+        &self,
+        recommendations: DataFrame,
+        novelty: f64,
+    ) -> Result<DataFrame, RecommenderError> {
+        let novelty = novelty.max(0f64);
+
+        if novelty == 0f64 {
+            return Ok(recommendations);
+        }
+
+        let popularity: Series = recommendations
+            .column("pid")?
+            .u64()?
+            .into_no_null_iter()
+            .map(|pid| {
+                let page = self.get_page_by_pid(pid)?;
+                match page[4] {
+                    AnyValue::UInt32(votes) => Ok((1f64 + votes as f64).powf(novelty)),
+                    _ => unreachable!(),
+                }
+            })
+            .collect::<Result<Vec<f64>, RecommenderError>>()?
+            .into_iter()
+            .collect();
+        let popularity = popularity.with_name("popularity");
+
+        // Disliked pages are pushed further down instead of being pulled towards 0
+        let discounted = when(col("weights").gt_eq(lit(0f64)))
+            .then(col("weights") / col("popularity"))
+            .otherwise(col("weights") * col("popularity"));
+
+        Ok(recommendations
+            .lazy()
+            .with_column(popularity.lit())
+            .with_column(discounted.alias("weights"))
+            .drop(["popularity"])
+            .sort(
+                ["weights"],
+                SortMultipleOptions::new().with_order_descending(true),
+            )
+            .collect()?)
+    }
+
+    /// Re-rank the top of a set of recommendations using maximal marginal relevance.
+    /// Diversity ranges from 0 (only use page weights, so nothing changes) to 1 (only avoid pages
+    /// similar to ones already picked). Pages are similar if they share tags or if the same users
+    /// rated them the same way.
+    pub fn diversify(
+        &self,
+        recommendations: DataFrame,
+        diversity: f64,
+    ) -> Result<DataFrame, RecommenderError> {
+        let diversity = diversity.clamp(0.0, 1.0);
+        let window = recommendations.height().min(DIVERSITY_WINDOW);
+
+        if diversity == 0.0 || window < 2 {
+            return Ok(recommendations);
+        }
+
+        let top = recommendations.head(Some(window));
+        let pids: Vec<u64> = top.column("pid")?.u64()?.into_no_null_iter().collect();
+        let weights: Vec<f64> = top
+            .column("weights")?
+            .f64()?
+            .iter()
+            .map(|weight| weight.unwrap_or(0f64))
+            .collect();
+
+        // Scale weights to 0-1 so that they are comparable to similarities
+        let max_weight = weights.iter().copied().fold(f64::MIN, f64::max);
+        let min_weight = weights.iter().copied().fold(f64::MAX, f64::min);
+        let weight_range = (max_weight - min_weight).max(f64::EPSILON);
+        let relevance: Vec<f64> = weights
+            .iter()
+            .map(|weight| (weight - min_weight) / weight_range)
+            .collect();
+
+        let similarity = self.get_page_similarity(&pids)?;
+
+        let mut order: Vec<IdxSize> = Vec::with_capacity(recommendations.height());
+        let mut remaining: Vec<usize> = (0..window).collect();
+        // Highest similarity of each remaining page to any page already picked
+        let mut max_similarity = vec![0f64; window];
+
+        while !remaining.is_empty() {
+            let (position, &picked) = remaining
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| {
+                    let a_score =
+                        (1f64 - diversity) * relevance[**a] - diversity * max_similarity[**a];
+                    let b_score =
+                        (1f64 - diversity) * relevance[**b] - diversity * max_similarity[**b];
+                    a_score.total_cmp(&b_score)
+                })
+                .expect("Loop only runs while pages remain");
+
+            remaining.swap_remove(position);
+            order.push(picked as IdxSize);
+
+            for &page in remaining.iter() {
+                max_similarity[page] = max_similarity[page].max(similarity[picked][page]);
+            }
+        }
+
+        order.extend(window as IdxSize..recommendations.height() as IdxSize);
+        let order = IdxCa::from_vec("order", order);
+
+        Ok(recommendations.take(&order)?)
+    }
+
+    // Get the similarity (0-1.0) of each given page to each other given page.
+    // This is the average of how much their tags overlap and the cosine similarity of their
+    // ratings.
+    fn get_page_similarity(&self, pids: &[u64]) -> Result<Vec<Vec<f64>>, RecommenderError> {
+        let page_ratings = self
+            .rating_frame
+            .clone()
+            .filter(col("pid").is_in(lit(Series::new("pids", pids))))
+            .collect()?;
+
+        // Rows may not come back in the same order, so keep track of where each one ended up
+        let rating_rows: PlHashMap<u64, usize> = page_ratings
+            .column("pid")?
+            .u64()?
+            .into_no_null_iter()
+            .enumerate()
+            .map(|(row, pid)| (pid, row))
+            .collect();
+
+        let ratings = page_ratings
+            .drop("pid")?
+            .to_ndarray::<Float64Type>(IndexOrder::C)?;
+        let norms: Vec<f64> = ratings
+            .rows()
+            .into_iter()
+            .map(|row| row.dot(&row).sqrt())
+            .collect();
+
+        let tag_sets = pids
             .iter()
             .map(|pid| {
-                let pid = match pid {
-                    AnyValue::UInt64(value) => value,
+                let page = self.get_page_by_pid(*pid)?;
+                match &page[3] {
+                    AnyValue::List(tags) => Ok(tags.u16()?.into_no_null_iter().collect()),
                     _ => unreachable!(),
+                }
+            })
+            .collect::<Result<Vec<PlHashSet<u16>>, RecommenderError>>()?;
+
+        let mut similarity = vec![vec![1f64; pids.len()]; pids.len()];
+        for i in 0..pids.len() {
+            for j in (i + 1)..pids.len() {
+                let shared_tags = tag_sets[i].intersection(&tag_sets[j]).count();
+                let all_tags = tag_sets[i].len() + tag_sets[j].len() - shared_tags;
+                let tag_similarity = if all_tags == 0 {
+                    0f64
+                } else {
+                    shared_tags as f64 / all_tags as f64
                 };
-                let page = self.get_page_by_pid(pid).expect("page should always exist");
-                let tag_list = match &page[3] {
-                    AnyValue::List(value) => value,
-                    _ => unreachable!(),
+
+                let rating_similarity = match (rating_rows.get(&pids[i]), rating_rows.get(&pids[j]))
+                {
+                    (Some(&a), Some(&b)) if norms[a] > 0f64 && norms[b] > 0f64 => {
+                        // Negative similarity means the pages are different, which is just as
+                        // good as unrelated for this
+                        (ratings.row(a).dot(&ratings.row(b)) / (norms[a] * norms[b])).max(0f64)
+                    }
+                    _ => 0f64,
                 };
 
-                // Find page tags from pid
-                let page_tags: PlHashSet<_> = tag_list
-                    .iter()
-                    .map(|value| match value {
-                        AnyValue::UInt16(int_val) => int_val,
-                        _ => unreachable!(),
-                    })
-                    .collect();
+                let page_similarity = (tag_similarity + rating_similarity) / 2f64;
+                similarity[i][j] = page_similarity;
+                similarity[j][i] = page_similarity;
+            }
+        }
 
-                page_tags.is_superset(&req_tag_set)
-            })
-            .collect();
+        Ok(similarity)
+    }
 
-        Ok(recommendations.filter(mask.bool()?)?)
+    /// Returns the most similar users to the given user, in descending order of similarity.
+    /// The frame has a uid column (as strings, matching the rating frame) and a similarity column.
+    pub fn get_neighbors(&self, uid: u64) -> Result<DataFrame, RecommenderError> {
+        let (user_vector, _) = self.get_user_vector(uid, &[])?;
+        self.get_neighbors_of(uid, &user_vector)
     }
 
-    /// Return a vector which can be used in a select on the rating frame to create page weights
-    fn get_similarity_selector(&self, uid: u64) -> Result<Vec<Expr>, RecommenderError> {
-        let user_similarity = self.get_user_similarity(uid)?;
-        let user_similarity = user_similarity.sort(
-            ["similarity"],
-            SortMultipleOptions::new().with_order_descending(true),
-        );
+    // Get the users most similar to the given vector of the given user's ratings
+    fn get_neighbors_of(
+        &self,
+        uid: u64,
+        user_vector: &Series,
+    ) -> Result<DataFrame, RecommenderError> {
+        let user_similarity = self.get_user_similarity(user_vector)?;
+        let user_similarity = user_similarity
+            // With local ratings, the user's own wiki votes are no longer an exact copy
+            .filter(col("uid").neq(lit(uid.to_string())))
+            .sort(
+                ["similarity"],
+                SortMultipleOptions::new().with_order_descending(true),
+            );
 
         // Drop all users which have a similarity of 1
         // These users provide literally nothing (since everything they would suggest has already
@@ -337,11 +726,79 @@ impl Recommender {
         let user_similarity = user_similarity.filter(col("similarity").lt(lit(0.999f64)));
 
         // Get the most similar non-exact-copy users
-        let user_similarity = user_similarity
+        Ok(user_similarity
             .clone()
             .limit(self.users_to_consider)
+            .collect()?)
+    }
+
+    // Get a Boolean Series, in the same order as the rating frame, of whether more than half of
+    // the given neighbors who voted on each page downvoted it
+    fn get_neighbor_disliked(
+        &self,
+        rating_frame: &LazyFrame,
+        neighbors: &DataFrame,
+    ) -> Result<Series, RecommenderError> {
+        let mut upvotes = Vec::with_capacity(neighbors.height());
+        let mut downvotes = Vec::with_capacity(neighbors.height());
+
+        for uid in neighbors.column("uid")?.str()?.into_no_null_iter() {
+            let unvote = match self.middle_norms.get(uid) {
+                Some(value) => *value,
+                None => return Err(RecommenderError::Bounds),
+            };
+
+            upvotes.push(
+                col(uid)
+                    .gt(lit(unvote + VOTE_UNCERTAINTY))
+                    .cast(DataType::UInt32),
+            );
+            downvotes.push(
+                col(uid)
+                    .lt(lit(unvote - VOTE_UNCERTAINTY))
+                    .cast(DataType::UInt32),
+            );
+        }
+
+        if upvotes.is_empty() {
+            return Ok(Series::new(
+                "disliked",
+                vec![false; page_count(rating_frame)?],
+            ));
+        }
+
+        let disliked = rating_frame
+            .clone()
+            .select([
+                sum_horizontal(upvotes)?.alias("upvotes"),
+                sum_horizontal(downvotes)?.alias("downvotes"),
+            ])
+            .select([col("downvotes").gt(col("upvotes")).alias("disliked")])
             .collect()?;
 
+        Ok(disliked.column("disliked")?.clone())
+    }
+
+    // Get the pids of pages from their rows in the page frame
+    fn get_pids(&self, pages: &RoaringBitmap) -> Result<Series, RecommenderError> {
+        let pids = self.page_frame.column("pid")?.u64()?;
+
+        Ok(Series::from_vec(
+            "pids",
+            pages
+                .iter()
+                .filter_map(|row| pids.get(row as usize))
+                .collect::<Vec<u64>>(),
+        ))
+    }
+
+    /// Return a vector which can be used in a select on the rating frame to create page weights
+    fn get_similarity_selector(
+        &self,
+        neighbors: &DataFrame,
+    ) -> Result<Vec<Expr>, RecommenderError> {
+        let user_similarity = neighbors;
+
         // Get the list of pages which the most similar users have read
         let similar_cols: Vec<_> = user_similarity["uid"]
             .iter()
@@ -393,7 +850,7 @@ impl Recommender {
     }
 
     // Get the similarity (0-1.0) of one user to every other user
-    fn get_user_similarity(&self, uid: u64) -> Result<LazyFrame, RecommenderError> {
+    fn get_user_similarity(&self, user_vector: &Series) -> Result<LazyFrame, RecommenderError> {
         // New LazyFrame with 2 cols: uid column, and similarity
         let rating_frame = self.rating_frame.clone().collect()?;
         let uids: Series = rating_frame
@@ -403,12 +860,11 @@ impl Recommender {
             .copied()
             .collect();
         let uids = uids.with_name("uid");
-        let selected = rating_frame[format!("{}", uid).as_str()].clone();
         let similarity: Series = rating_frame
             .iter()
             .skip(1)
             .map(|a| {
-                selected
+                user_vector
                     .dot(a)
                     .expect("Series of the same DF should have the same dimensions")
             })
@@ -417,6 +873,105 @@ impl Recommender {
         Ok(DataFrame::new(vec![uids, similarity])?.lazy())
     }
 
+    // Get a user's column of the rating frame, and the value of a non-vote in it.
+    // Local ratings replace any wiki votes on the same pages, and the column is normalized again
+    // the same way as in `normalize_rating_frame`.
+    fn get_user_vector(
+        &self,
+        uid: u64,
+        ratings: &[LocalRating],
+    ) -> Result<(Series, f64), RecommenderError> {
+        let uid_str = format!("{}", uid);
+        let unvote = match self.middle_norms.get(&uid_str) {
+            Some(value) => *value,
+            None => return Err(RecommenderError::UserNotFound),
+        };
+
+        let frame = self
+            .rating_frame
+            .clone()
+            .select([col("pid"), col(&uid_str)])
+            .collect()?;
+        let column = frame.column(&uid_str)?;
+
+        if ratings.is_empty() {
+            return Ok((column.clone(), unvote));
+        }
+
+        let local_ratings: PlHashMap<u64, f64> = ratings
+            .iter()
+            .map(|rating| {
+                let value = match rating.upvote {
+                    true => self.upvote_weight,
+                    false => -self.downvote_weight,
+                };
+                (rating.pid, value)
+            })
+            .collect();
+
+        // Undo the normalization, since only whether each page was voted on matters
+        let raw: Vec<f64> = frame
+            .column("pid")?
+            .u64()?
+            .into_no_null_iter()
+            .zip(column.f64()?.into_no_null_iter())
+            .map(|(pid, value)| match local_ratings.get(&pid) {
+                Some(rating) => *rating,
+                None if value > unvote + VOTE_UNCERTAINTY => self.upvote_weight,
+                None if value < unvote - VOTE_UNCERTAINTY => -self.downvote_weight,
+                None => 0f64,
+            })
+            .collect();
+
+        // The frame was normalized with an extra row of non-votes, which is added back here
+        let mean = raw.iter().sum::<f64>() / (raw.len() + 1) as f64;
+        let norm =
+            (raw.iter().map(|value| (value - mean).powi(2)).sum::<f64>() + mean.powi(2)).sqrt();
+
+        if norm == 0f64 {
+            return Ok((column.clone(), unvote));
+        }
+
+        let normalized: Vec<f64> = raw.iter().map(|value| (value - mean) / norm).collect();
+
+        Ok((Series::from_vec(&uid_str, normalized), -mean / norm))
+    }
+
+    /// Returns the novelty to use when none is requested
+    pub fn get_novelty(&self) -> f64 {
+        self.novelty
+    }
+
+    /// Returns how many users, pages, votes, and tags this recommender was built from
+    pub fn get_model_size(&self) -> Result<ModelSize, RecommenderError> {
+        let votes = self
+            .page_frame
+            .column("votes")?
+            .u32()?
+            .into_no_null_iter()
+            .map(u64::from)
+            .sum();
+
+        Ok(ModelSize {
+            users: self.user_frame.height(),
+            pages: self.page_frame.height(),
+            votes,
+            tags: self.tags_frame.height(),
+        })
+    }
+
+    /// Returns the unix time the scrape this recommender's data came from started.
+    /// Scrapes from before this was recorded do not have it.
+    pub fn get_scraped_at(&self) -> Option<u64> {
+        self.scraped_at
+    }
+
+    /// Returns a hash of the options this recommender ranks pages with.
+    /// Rankings from recommenders with different engine hashes should not be mixed.
+    pub fn get_engine_hash(&self) -> u64 {
+        self.engine_hash
+    }
+
     pub fn get_tag_by_id(&self, index: u16) -> Option<String> {
         match self.tags_frame.get(index.into())?.first()? {
             AnyValue::String(value) => Some(String::from(*value)),
@@ -447,6 +1002,18 @@ impl Recommender {
     }
 }
 
+// Get the number of pages in a rating frame
+fn page_count(rating_frame: &LazyFrame) -> Result<usize, RecommenderError> {
+    Ok(rating_frame
+        .clone()
+        .select([col("pid").count()])
+        .collect()?
+        .column("pid")?
+        .u32()?
+        .get(0)
+        .unwrap_or(0) as usize)
+}
+
 fn set_up_user_frame(user_file: &str) -> Result<DataFrame, RecommenderError> {
     let file = File::open(user_file)?;
     let mut user_df = ParquetReader::new(file).finish()?;
@@ -457,13 +1024,27 @@ fn set_up_user_frame(user_file: &str) -> Result<DataFrame, RecommenderError> {
     Ok(user_df)
 }
 
-fn set_up_page_frame(page_file: &str) -> Result<DataFrame, RecommenderError> {
+fn set_up_page_frame(
+    page_file: &str,
+    rating_lf: &LazyFrame,
+) -> Result<DataFrame, RecommenderError> {
     let args = ScanArgsParquet::default();
     let page_lf = LazyFrame::scan_parquet(page_file, args)?;
 
     // Remove duplicates. Fixes issues with stuff like "The Troll"
     let page_lf = page_lf.unique(Some(vec!["pid".into()]), UniqueKeepStrategy::Any);
 
+    // Count every vote, including from users who will be discarded, since this is used as a
+    // measure of popularity
+    let vote_counts = rating_lf
+        .clone()
+        .group_by(["pid"])
+        .agg([col("rating").count().cast(DataType::UInt32).alias("votes")]);
+
+    let page_lf = page_lf
+        .left_join(vote_counts, col("pid"), col("pid"))
+        .with_column(col("votes").fill_null(lit(0u32)));
+
     Ok(page_lf.collect()?)
 }
 
@@ -480,6 +1061,19 @@ fn set_up_rating_frame(rating_file: &str) -> Result<LazyFrame, RecommenderError>
     Ok(rating_lf)
 }
 
+// Get when the scrape which made an output file started, from the metadata the scraper saves in it
+fn read_scraped_at(file_name: &str) -> Result<Option<u64>, RecommenderError> {
+    let mut reader = ParquetReader::new(File::open(file_name)?);
+    let metadata = reader.get_metadata()?;
+
+    Ok(metadata
+        .key_value_metadata
+        .iter()
+        .flatten()
+        .find(|pair| pair.key == SCRAPED_AT_KEY)
+        .and_then(|pair| pair.value.as_ref()?.parse().ok()))
+}
+
 fn set_up_tags_frame(tags_file: &str) -> Result<DataFrame, RecommenderError> {
     let file = File::open(tags_file)?;
     let tags_df = ParquetReader::new(file).finish()?;
@@ -499,6 +1093,46 @@ mod tests {
 
     const OUTPUT_DIR: &str = "../output";
 
+    #[test]
+    fn find_non_vote_values() {
+        let options = get_test_options();
+        let rec = Recommender::new_with_options(&options).expect("Recommender not created");
+
+        let vote_counts = set_up_rating_frame(options.get_votes_file())
+            .expect("Votes not read")
+            .group_by(["uid"])
+            .agg([col("rating").count().cast(DataType::UInt64).alias("votes")])
+            .collect()
+            .expect("Votes not counted");
+        let ratings = rec
+            .rating_frame
+            .clone()
+            .collect()
+            .expect("Ratings collected");
+
+        // Every value other than a user's non-vote value should be one of their votes
+        for (uid, count) in vote_counts["uid"]
+            .u64()
+            .unwrap()
+            .into_no_null_iter()
+            .zip(vote_counts["votes"].u64().unwrap().into_no_null_iter())
+        {
+            let uid = uid.to_string();
+            // Users with too few votes are not in the model
+            let Some(unvote) = rec.middle_norms.get(&uid) else {
+                continue;
+            };
+
+            let votes = ratings[uid.as_str()]
+                .f64()
+                .unwrap()
+                .into_no_null_iter()
+                .filter(|value| (value - unvote).abs() > VOTE_UNCERTAINTY)
+                .count();
+            assert_eq!(votes as u64, count, "{} has the wrong non-vote value", uid);
+        }
+    }
+
     #[test]
     fn get_recommendation() {
         let options = get_test_options();
@@ -517,10 +1151,596 @@ mod tests {
             _ => unreachable!(),
         };
 
-        rec.get_recommendations_by_uid(column, Vec::new(), Vec::new())
+        rec.get_recommendations_by_uid(column, &TagFilter::default(), Vec::new())
+            .expect("Recommendation not made")
+            .collect()
+            .expect("Not collected");
+    }
+
+    #[test]
+    fn diversify_recommendation() {
+        let options = get_test_options();
+        let rec = Recommender::new_with_options(&options).expect("Recommender not created");
+
+        let uid = match rec.user_frame.get(0).expect("Row not found")[2] {
+            AnyValue::UInt64(value) => value,
+            _ => unreachable!(),
+        };
+
+        let recs = rec
+            .get_recommendations_by_uid(uid, &TagFilter::default(), Vec::new())
+            .expect("Recommendation not made")
+            .collect()
+            .expect("Not collected");
+
+        let unchanged = rec.diversify(recs.clone(), 0f64).expect("Not diversified");
+        assert!(recs.equals_missing(&unchanged));
+
+        let diversified = rec.diversify(recs.clone(), 0.8).expect("Not diversified");
+        assert_eq!(recs.height(), diversified.height());
+
+        // Only the order should change
+        let sorted_pids = |frame: &DataFrame| {
+            let mut pids: Vec<u64> = frame["pid"].u64().unwrap().into_no_null_iter().collect();
+            pids.sort();
+            pids
+        };
+        assert_eq!(sorted_pids(&recs), sorted_pids(&diversified));
+
+        // The top pages should be less alike than before
+        let mean_similarity = |frame: &DataFrame| {
+            let pids: Vec<u64> = frame["pid"]
+                .u64()
+                .unwrap()
+                .into_no_null_iter()
+                .take(10)
+                .collect();
+            let similarity = rec
+                .get_page_similarity(&pids)
+                .expect("Similarity not found");
+            let pairs: Vec<f64> = (0..pids.len())
+                .flat_map(|i| ((i + 1)..pids.len()).map(move |j| (i, j)))
+                .map(|(i, j)| similarity[i][j])
+                .collect();
+            pairs.iter().sum::<f64>() / pairs.len() as f64
+        };
+        assert!(mean_similarity(&diversified) < mean_similarity(&recs));
+    }
+
+    #[test]
+    fn separate_similar_pages() {
+        let rec = get_fixture_recommender();
+
+        // Pages 10 and 30 share most of their tags, and page 20 shares nothing with either
+        let recs =
+            df!("pid" => [10u64, 30, 20], "weights" => [1.0, 0.5, 0.0]).expect("Recs not made");
+        let pids = |frame: &DataFrame| -> Vec<u64> {
+            frame["pid"].u64().unwrap().into_no_null_iter().collect()
+        };
+
+        let diversified = rec.diversify(recs.clone(), 0.8).expect("Not diversified");
+        assert_eq!(pids(&diversified), vec![10, 20, 30]);
+
+        // With only a little diversity, the better page still comes first
+        let diversified = rec.diversify(recs, 0.1).expect("Not diversified");
+        assert_eq!(pids(&diversified), vec![10, 30, 20]);
+    }
+
+    #[test]
+    fn discount_popular_pages() {
+        let options = get_test_options();
+        let rec = Recommender::new_with_options(&options).expect("Recommender not created");
+
+        let uid = match rec.user_frame.get(0).expect("Row not found")[2] {
+            AnyValue::UInt64(value) => value,
+            _ => unreachable!(),
+        };
+
+        let recs = rec
+            .get_recommendations_by_uid(uid, &TagFilter::default(), Vec::new())
             .expect("Recommendation not made")
             .collect()
             .expect("Not collected");
+
+        let discounted = rec
+            .discount_popularity(recs.clone(), 1f64)
+            .expect("Not discounted");
+        assert_eq!(recs.height(), discounted.height());
+
+        let weights: Vec<f64> = discounted["weights"]
+            .f64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert!(weights.windows(2).all(|pair| pair[0] >= pair[1]));
+    }
+
+    #[test]
+    fn downvote_exclusion() {
+        let options = get_test_options();
+        let mut rec = Recommender::new_with_options(&options).expect("Recommender not created");
+
+        let uids: Vec<u64> = rec.user_frame["uid"]
+            .u64()
+            .unwrap()
+            .into_no_null_iter()
+            .take(10)
+            .collect();
+        let ratings = rec
+            .rating_frame
+            .clone()
+            .collect()
+            .expect("Ratings collected");
+        let pid_rows: PlHashMap<u64, usize> = ratings["pid"]
+            .u64()
+            .unwrap()
+            .into_no_null_iter()
+            .enumerate()
+            .map(|(row, pid)| (pid, row))
+            .collect();
+
+        let weights = |ranking: &Ranking| -> PlHashMap<u64, f64> {
+            ranking.candidates["pid"]
+                .u64()
+                .unwrap()
+                .into_no_null_iter()
+                .zip(
+                    ranking.candidates["weights"]
+                        .f64()
+                        .unwrap()
+                        .into_no_null_iter(),
+                )
+                .collect()
+        };
+
+        let mut total_excluded = 0;
+        for uid in uids {
+            rec.downvote_exclusion = false;
+            let without = rec.get_ranking_by_uid(uid).expect("Ranking not made");
+            rec.downvote_exclusion = true;
+            let with = rec.get_ranking_by_uid(uid).expect("Ranking not made");
+
+            // Whether most of the similar users who voted on a page downvoted it
+            let neighbor_uids: Vec<&str> = with.neighbors["uid"]
+                .str()
+                .unwrap()
+                .into_no_null_iter()
+                .collect();
+            let mostly_downvoted = |pid: &u64| {
+                let row = pid_rows[pid];
+                let (mut upvotes, mut downvotes) = (0, 0);
+                for neighbor in &neighbor_uids {
+                    let norm = rec.middle_norms[*neighbor];
+                    let rating = ratings[*neighbor].f64().unwrap().get(row).unwrap_or(norm);
+                    if rating > norm + VOTE_UNCERTAINTY {
+                        upvotes += 1;
+                    } else if rating < norm - VOTE_UNCERTAINTY {
+                        downvotes += 1;
+                    }
+                }
+                downvotes > upvotes
+            };
+
+            // Exclusion only removes pages, and the ones it removes are the mostly downvoted ones
+            let (without, with) = (weights(&without), weights(&with));
+            for (pid, weight) in &with {
+                assert_eq!(without.get(pid), Some(weight));
+                assert!(!mostly_downvoted(pid));
+            }
+            for pid in without.keys().filter(|pid| !with.contains_key(*pid)) {
+                assert!(mostly_downvoted(pid));
+                total_excluded += 1;
+            }
+        }
+
+        assert!(total_excluded > 0, "No pages were excluded");
+    }
+
+    #[test]
+    fn filter_kept_ranking() {
+        let options = get_test_options();
+        let rec = Recommender::new_with_options(&options).expect("Recommender not created");
+
+        let uid = rec.user_frame["uid"].u64().unwrap().get(0).unwrap();
+        let ranking = rec.get_ranking_by_uid(uid).expect("Ranking not made");
+
+        // Ban the top few pages, then check that filtering the same ranking again gives the
+        // same result as ranking from scratch
+        let bans: Vec<u64> = ranking.candidates["pid"]
+            .u64()
+            .unwrap()
+            .into_no_null_iter()
+            .take(5)
+            .collect();
+
+        let filtered = rec
+            .filter_ranking(&ranking, &TagFilter::default(), bans.clone())
+            .expect("Ranking not filtered")
+            .collect()
+            .expect("Not collected");
+        let fresh = rec
+            .get_recommendations_by_uid(uid, &TagFilter::default(), bans)
+            .expect("Recommendation not made")
+            .collect()
+            .expect("Not collected");
+
+        assert_eq!(filtered.height(), ranking.len() - 5);
+        assert!(filtered.equals(&fresh));
+    }
+
+    #[test]
+    fn filter_tags_before_weighting() {
+        let options = get_test_options();
+        let rec = Recommender::new_with_options(&options).expect("Recommender not created");
+
+        let uid = rec.user_frame["uid"].u64().unwrap().get(0).unwrap();
+        let ranking = rec.get_ranking_by_uid(uid).expect("Ranking not made");
+        let tags = rec
+            .parse_tag_filter("scp AND NOT keter")
+            .expect("Filter not parsed");
+
+        // Only weighting pages with the tags must give the same result as filtering them later
+        let filtered = rec
+            .filter_ranking(&ranking, &tags, Vec::new())
+            .expect("Ranking not filtered")
+            .collect()
+            .expect("Not collected");
+        let restricted = rec
+            .get_recommendations_by_uid(uid, &tags, Vec::new())
+            .expect("Recommendation not made")
+            .collect()
+            .expect("Not collected");
+
+        assert!(filtered.height() < ranking.len());
+        assert!(filtered.equals(&restricted));
+    }
+
+    #[test]
+    fn rate_pages_locally() {
+        let options = get_test_options();
+        let rec = Recommender::new_with_options(&options).expect("Recommender not created");
+
+        let uid = rec.user_frame["uid"].u64().unwrap().get(0).unwrap();
+        let ranking = rec.get_ranking_by_uid(uid).expect("Ranking not made");
+        let (wiki_vector, unvote) = rec.get_user_vector(uid, &[]).expect("Vector not made");
+
+        // Rating pages the same as they were voted on changes nothing
+        let pids = rec
+            .rating_frame
+            .clone()
+            .select([col("pid")])
+            .collect()
+            .unwrap();
+        let same_ratings: Vec<LocalRating> = pids["pid"]
+            .u64()
+            .unwrap()
+            .into_no_null_iter()
+            .zip(wiki_vector.f64().unwrap().into_no_null_iter())
+            .filter(|(_, value)| (value - unvote).abs() > VOTE_UNCERTAINTY)
+            .map(|(pid, value)| LocalRating {
+                pid,
+                upvote: value > unvote,
+            })
+            .collect();
+        let (same_vector, _) = rec
+            .get_user_vector(uid, &same_ratings)
+            .expect("Vector not made");
+        for (wiki, same) in wiki_vector
+            .f64()
+            .unwrap()
+            .into_no_null_iter()
+            .zip(same_vector.f64().unwrap().into_no_null_iter())
+        {
+            assert!((wiki - same).abs() < 1e-9);
+        }
+
+        // Rated pages are never recommended, and change who the similar users are
+        let ratings: Vec<LocalRating> = ranking.candidates["pid"]
+            .u64()
+            .unwrap()
+            .into_no_null_iter()
+            .take(5)
+            .map(|pid| LocalRating { pid, upvote: true })
+            .collect();
+        let rated = rec
+            .get_ranking_with_ratings(uid, &ratings)
+            .expect("Ranking not made");
+
+        assert_eq!(rated.len(), ranking.len() - 5);
+        let rated_pids = rated.candidates["pid"].u64().unwrap();
+        assert!(ratings
+            .iter()
+            .all(|rating| !rated_pids.into_no_null_iter().any(|pid| pid == rating.pid)));
+        assert!(!rated.neighbors.equals(&ranking.neighbors));
+    }
+
+    #[test]
+    fn describe_taste() {
+        let options = get_test_options();
+        let rec = Recommender::new_with_options(&options).expect("Recommender not created");
+
+        let uid = rec.user_frame["uid"].u64().unwrap().get(0).unwrap();
+        let profile = rec.get_taste_profile(uid).expect("Profile not made");
+
+        assert_eq!(profile.uid, uid);
+        assert_eq!(profile.votes, profile.upvotes + profile.downvotes);
+        assert!(profile.votes as u16 >= options.get_min_votes());
+        assert!((0f64..=1f64).contains(&profile.upvote_ratio));
+        assert!(profile.top_tags.len() <= 10);
+        assert!(profile
+            .top_tags
+            .windows(2)
+            .all(|tags| tags[0].affinity.partial_cmp(&tags[1].affinity) != Some(Ordering::Less)));
+        assert!(profile
+            .more_than_average
+            .iter()
+            .all(|tag| tag.share > tag.wiki_share));
+        assert!(profile
+            .less_than_average
+            .iter()
+            .all(|tag| tag.share < tag.wiki_share));
+        assert!(!profile.similar_users.is_empty());
+        assert!(!profile
+            .similar_users
+            .iter()
+            .any(|user| user.name == profile.name));
+    }
+
+    #[test]
+    fn compare_users() {
+        let options = get_test_options();
+        let rec = Recommender::new_with_options(&options).expect("Recommender not created");
+
+        let uids = rec.user_frame["uid"].u64().unwrap();
+        let (a, b) = (uids.get(0).unwrap(), uids.get(1).unwrap());
+        let forward = rec.compare_users(a, b).expect("Users not compared");
+        let backward = rec.compare_users(b, a).expect("Users not compared");
+
+        // Comparing the other way around only swaps which user is which
+        assert!((forward.similarity - backward.similarity).abs() < 1e-9);
+        assert_eq!(forward.agreements, backward.agreements);
+        assert_eq!(forward.both_liked, backward.both_liked);
+        assert_eq!(forward.a_liked_b_disliked, backward.b_liked_a_disliked);
+        assert_eq!(
+            forward.liked_by_a_unread_by_b,
+            backward.liked_by_b_unread_by_a
+        );
+        assert_eq!(forward.shared_tags, backward.shared_tags);
+
+        let votes_b: PlHashMap<u64, bool> = rec.get_votes(b).unwrap().into_iter().collect();
+        assert!(forward
+            .both_liked
+            .iter()
+            .all(|pid| votes_b.get(pid) == Some(&true)));
+        assert!(forward
+            .liked_by_a_unread_by_b
+            .iter()
+            .all(|pid| !votes_b.contains_key(pid)));
+    }
+
+    #[test]
+    fn recommend_for_group() {
+        let options = get_test_options();
+        let rec = Recommender::new_with_options(&options).expect("Recommender not created");
+
+        let uids = rec.user_frame["uid"].u64().unwrap();
+        let group = [
+            uids.get(0).unwrap(),
+            uids.get(1).unwrap(),
+            uids.get(2).unwrap(),
+        ];
+        let rankings: Vec<Ranking> = group
+            .iter()
+            .map(|uid| rec.get_ranking_by_uid(*uid).expect("Ranking not made"))
+            .collect();
+
+        let average = rec
+            .get_recommendations_for_group(&group, GroupStrategy::Average)
+            .expect("Group recommendation not made");
+        let least = rec
+            .get_recommendations_for_group(&group, GroupStrategy::LeastMisery)
+            .expect("Group recommendation not made");
+        let most = rec
+            .get_recommendations_for_group(&group, GroupStrategy::MostPleasure)
+            .expect("Group recommendation not made");
+
+        // Only pages nobody has voted on are kept, whatever the strategy
+        for ranking in &rankings {
+            let candidates: PlHashSet<u64> = ranking.candidates["pid"]
+                .u64()
+                .unwrap()
+                .into_no_null_iter()
+                .collect();
+            assert!(average["pid"]
+                .u64()
+                .unwrap()
+                .into_no_null_iter()
+                .all(|pid| candidates.contains(&pid)));
+        }
+        assert_eq!(average.height(), least.height());
+        assert_eq!(average.height(), most.height());
+
+        // The least any member likes a page is never more than the average, or the most
+        let weights = |frame: &DataFrame| -> PlHashMap<u64, f64> {
+            frame["pid"]
+                .u64()
+                .unwrap()
+                .into_no_null_iter()
+                .zip(frame["weights"].f64().unwrap().into_no_null_iter())
+                .collect()
+        };
+        let (average, least, most) = (weights(&average), weights(&least), weights(&most));
+        for (pid, weight) in &average {
+            assert!(least[pid] <= *weight + 1e-9);
+            assert!(most[pid] >= *weight - 1e-9);
+        }
+    }
+
+    #[test]
+    fn explain_recommendations() {
+        let rec = get_fixture_recommender();
+
+        // Similarity alone would put ann first, but bob and cat rated the page higher
+        let neighbors = df!(
+            "uid" => ["1", "2", "3", "4", "5"],
+            "similarity" => [0.9, 0.8, 0.3, 0.7, 0.6],
+        )
+        .expect("Neighbors not made");
+        let ranking = Ranking {
+            neighbors,
+            ..Ranking::empty(6)
+        };
+        let recs = df!("pid" => [10u64, 20], "weights" => [1.5, -0.2]).expect("Recs not made");
+
+        let explanations = rec.explain(&ranking, &recs).expect("Explanation not made");
+        assert_eq!(explanations.len(), 2);
+
+        let first = &explanations[0];
+        assert_eq!(first.score, 1.5);
+        assert_eq!(first.neighbor_upvotes, 4);
+        assert_eq!(first.neighbor_downvotes, 1);
+        // Eve also upvoted, but added the least weight, so only three are named
+        let named: Vec<(&str, f64)> = first
+            .top_neighbors
+            .iter()
+            .map(|neighbor| (neighbor.name.as_str(), neighbor.similarity))
+            .collect();
+        assert_eq!(named, vec![("bob", 0.8), ("cat", 0.3), ("ann", 0.9)]);
+        // _licensebox is shared too, but is not a content tag
+        assert_eq!(first.shared_tags, vec![String::from("scp")]);
+
+        let second = &explanations[1];
+        assert_eq!(second.score, -0.2);
+        assert_eq!(second.neighbor_upvotes, 0);
+        assert_eq!(second.neighbor_downvotes, 2);
+        assert!(second.top_neighbors.is_empty());
+        assert!(second.shared_tags.is_empty());
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let options = get_test_options();
+        let mut rec = Recommender::new_with_options(&options).expect("Recommender not created");
+        rec.scraped_at = Some(1_726_099_200);
+
+        // Named uniquely so that test runs at the same time do not share a file
+        let snapshot_file = format!(
+            "{}/lotus_test_{}.snapshot",
+            std::env::temp_dir().display(),
+            uuid::Uuid::new_v4()
+        );
+        rec.save_snapshot(&snapshot_file, &options)
+            .expect("Snapshot not saved");
+        let loaded =
+            Recommender::new_from_snapshot(&snapshot_file, &options).expect("Snapshot not loaded");
+
+        assert_eq!(rec.middle_norms, loaded.middle_norms);
+        assert_eq!(rec.page_map, loaded.page_map);
+        assert_eq!(loaded.get_scraped_at(), Some(1_726_099_200));
+        assert!(rec.page_frame.equals_missing(&loaded.page_frame));
+        assert!(rec.user_frame.equals_missing(&loaded.user_frame));
+        assert!(rec.tags_frame.equals_missing(&loaded.tags_frame));
+        assert!(rec
+            .rating_frame
+            .clone()
+            .collect()
+            .expect("Ratings collected")
+            .equals_missing(
+                &loaded
+                    .rating_frame
+                    .clone()
+                    .collect()
+                    .expect("Ratings collected")
+            ));
+
+        // The saved indexes should find the same things as the ones they were saved from
+        for query in ["scp", "the", "sc 17"] {
+            assert_eq!(
+                rec.search_pages(query, 50),
+                loaded.search_pages(query, 50),
+                "{} found different pages",
+                query
+            );
+        }
+        let user_name = rec.user_frame["name"].str().unwrap().get(0).unwrap();
+        for query in [user_name, "a", "kelvn"] {
+            let uids = |rec: &Recommender| -> Vec<u64> {
+                rec.search_users(query, 50)
+                    .iter()
+                    .map(|user| user.uid)
+                    .collect()
+            };
+            assert_eq!(uids(&rec), uids(&loaded), "{} found different users", query);
+        }
+        let filter = rec
+            .parse_tag_filter("scp AND NOT (joke OR tale)")
+            .expect("Filter not parsed");
+        assert_eq!(
+            rec.tag_index.get_matching_pages(&filter),
+            loaded.tag_index.get_matching_pages(&filter)
+        );
+
+        // Options with a different model should not be able to use the snapshot
+        let other_options = get_test_options().with_min_votes(101);
+        assert!(Recommender::new_from_snapshot(&snapshot_file, &other_options).is_err());
+
+        std::fs::remove_file(&snapshot_file).expect("Snapshot not removed");
+    }
+
+    // A recommender built by hand, where every user's non-vote value is 0.
+    // User 6 upvoted page 30, and has not voted on pages 10 and 20.
+    fn get_fixture_recommender() -> Recommender {
+        let options = RecommenderOptions::new();
+
+        let user_frame = df!(
+            "name" => ["ann", "bob", "cat", "dan", "eve", "you"],
+            "url" => ["ann", "bob", "cat", "dan", "eve", "you"],
+            "uid" => [1u64, 2, 3, 4, 5, 6],
+        )
+        .expect("Users not made");
+        let tags_frame =
+            df!("tag" => ["scp", "horror", "_licensebox", "joke"]).expect("Tags not made");
+        let page_frame = df!(
+            "name" => ["Page A", "Page B", "Page C"],
+            "url" => ["page-a", "page-b", "page-c"],
+            "pid" => [10u64, 20, 30],
+            "tags" => [
+                Series::new("", [0u16, 1, 2]),
+                Series::new("", [3u16]),
+                Series::new("", [0u16, 2]),
+            ],
+            "votes" => [5u32, 2, 1],
+        )
+        .expect("Pages not made");
+        let rating_frame = df!(
+            "pid" => [10u64, 20, 30],
+            "1" => [0.2, -0.5, 0.0],
+            "2" => [0.5, -0.5, 0.0],
+            "3" => [0.9, 0.0, 0.0],
+            "4" => [-0.4, 0.0, 0.0],
+            "5" => [0.1, 0.0, 0.0],
+            "6" => [0.0, 0.0, 0.5],
+        )
+        .expect("Ratings not made");
+
+        Recommender {
+            middle_norms: (1..=6).map(|uid| (uid.to_string(), 0.0)).collect(),
+            page_map: [(10, 0), (20, 1), (30, 2)].into_iter().collect(),
+            page_index: PageIndex::new(&page_frame, &tags_frame).expect("Page index not made"),
+            tag_index: TagIndex::new(&page_frame, &tags_frame).expect("Tag index not made"),
+            page_frame,
+            rating_frame: rating_frame.lazy(),
+            tags_frame,
+            user_index: UserIndex::new(&user_frame).expect("User index not made"),
+            user_frame,
+            users_to_consider: options.get_users_to_consider(),
+            novelty: options.get_novelty(),
+            upvote_weight: options.get_upvote_weight(),
+            downvote_weight: options.get_downvote_weight(),
+            downvote_exclusion: options.get_downvote_exclusion(),
+            engine_hash: options.get_engine_hash(),
+            scraped_at: None,
+        }
     }
 
     fn get_test_options() -> RecommenderOptions {
@@ -0,0 +1,156 @@
+use super::{Comparison, Recommender, RecommenderError};
+use polars::datatypes::PlHashMap;
+use tracing::instrument;
+
+/// Most pages in each list of a comparison
+const COMPARED_PAGES: usize = 20;
+
+/// Most tags in the shared favorite tags of a comparison
+const COMPARED_TAGS: usize = 10;
+
+impl Recommender {
+    /// Compare the taste of two users: how similar the recommender thinks they are, the pages they
+    /// agree and disagree on, the tags they both like, and the pages each liked which the other
+    /// has not voted on. Lists of pages are ordered by popularity, most votes first.
+    #[instrument(skip(self))]
+    pub fn compare_users(&self, uid_a: u64, uid_b: u64) -> Result<Comparison, RecommenderError> {
+        let (vector_a, _) = self.get_user_vector(uid_a, &[])?;
+        let (vector_b, _) = self.get_user_vector(uid_b, &[])?;
+        let similarity = vector_a.dot(&vector_b)?;
+
+        let votes_a = self.get_votes(uid_a)?;
+        let votes_b: PlHashMap<u64, bool> = self.get_votes(uid_b)?.into_iter().collect();
+
+        let mut comparison = Comparison {
+            a: self.get_usernames(&[uid_a.to_string().as_str()])?.remove(0),
+            b: self.get_usernames(&[uid_b.to_string().as_str()])?.remove(0),
+            similarity,
+            agreements: 0,
+            disagreements: 0,
+            both_liked: Vec::new(),
+            both_disliked: Vec::new(),
+            a_liked_b_disliked: Vec::new(),
+            b_liked_a_disliked: Vec::new(),
+            shared_tags: self.get_shared_tags(&votes_a, &votes_b)?,
+            liked_by_a_unread_by_b: Vec::new(),
+            liked_by_b_unread_by_a: Vec::new(),
+        };
+
+        for (pid, upvote_a) in &votes_a {
+            let list = match (upvote_a, votes_b.get(pid)) {
+                (true, Some(true)) => &mut comparison.both_liked,
+                (false, Some(false)) => &mut comparison.both_disliked,
+                (true, Some(false)) => &mut comparison.a_liked_b_disliked,
+                (false, Some(true)) => &mut comparison.b_liked_a_disliked,
+                (true, None) => &mut comparison.liked_by_a_unread_by_b,
+                (false, None) => continue,
+            };
+            list.push(*pid);
+        }
+
+        let read_by_a: PlHashMap<u64, bool> = votes_a.into_iter().collect();
+        comparison.liked_by_b_unread_by_a = votes_b
+            .iter()
+            .filter(|(pid, upvote)| **upvote && !read_by_a.contains_key(*pid))
+            .map(|(pid, _)| *pid)
+            .collect();
+
+        comparison.agreements =
+            (comparison.both_liked.len() + comparison.both_disliked.len()) as u32;
+        comparison.disagreements =
+            (comparison.a_liked_b_disliked.len() + comparison.b_liked_a_disliked.len()) as u32;
+
+        for list in [
+            &mut comparison.both_liked,
+            &mut comparison.both_disliked,
+            &mut comparison.a_liked_b_disliked,
+            &mut comparison.b_liked_a_disliked,
+            &mut comparison.liked_by_a_unread_by_b,
+            &mut comparison.liked_by_b_unread_by_a,
+        ] {
+            self.sort_by_popularity(list)?;
+            list.truncate(COMPARED_PAGES);
+        }
+
+        Ok(comparison)
+    }
+
+    // Get the tags both users like, from the upvotes and downvotes each gave to pages with them.
+    // Tags both like the most come first.
+    fn get_shared_tags(
+        &self,
+        votes_a: &[(u64, bool)],
+        votes_b: &PlHashMap<u64, bool>,
+    ) -> Result<Vec<String>, RecommenderError> {
+        let tags: Vec<&str> = self
+            .tags_frame
+            .column("tag")?
+            .str()?
+            .into_no_null_iter()
+            .collect();
+        let page_tags = self.page_frame.column("tags")?.list()?;
+
+        // Upvotes minus downvotes on pages with each tag, as a fraction of all of a user's votes
+        let tag_affinity = |votes: &mut dyn Iterator<Item = (u64, bool)>| {
+            let mut affinity = vec![0f64; tags.len()];
+            let mut count = 0usize;
+
+            for (pid, upvote) in votes {
+                count += 1;
+                let Some(page) = self
+                    .page_map
+                    .get(&pid)
+                    .and_then(|row| page_tags.get_as_series(*row))
+                else {
+                    continue;
+                };
+                for tag in page.u16()?.into_no_null_iter() {
+                    if let Some(value) = affinity.get_mut(tag as usize) {
+                        *value += if upvote { 1f64 } else { -1f64 };
+                    }
+                }
+            }
+
+            let count = count.max(1) as f64;
+            Ok::<_, RecommenderError>(
+                affinity
+                    .into_iter()
+                    .map(|value| value / count)
+                    .collect::<Vec<f64>>(),
+            )
+        };
+
+        let affinity_a = tag_affinity(&mut votes_a.iter().copied())?;
+        let affinity_b = tag_affinity(&mut votes_b.iter().map(|(pid, upvote)| (*pid, *upvote)))?;
+
+        let mut shared: Vec<(f64, &str)> = tags
+            .iter()
+            .zip(affinity_a.iter().zip(affinity_b.iter()))
+            // Tags starting with an underscore are for the wiki's own use, like "_licensebox"
+            .filter(|(tag, (a, b))| !tag.starts_with('_') && **a > 0f64 && **b > 0f64)
+            .map(|(tag, (a, b))| (a.min(*b), *tag))
+            .collect();
+        shared.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+        Ok(shared
+            .into_iter()
+            .take(COMPARED_TAGS)
+            .map(|(_, tag)| String::from(tag))
+            .collect())
+    }
+
+    // Sort pids by the number of votes on their pages, most first
+    fn sort_by_popularity(&self, pids: &mut [u64]) -> Result<(), RecommenderError> {
+        let votes = self.page_frame.column("votes")?.u32()?;
+        let popularity = |pid: &u64| {
+            self.page_map
+                .get(pid)
+                .and_then(|row| votes.get(*row))
+                .unwrap_or(0)
+        };
+
+        pids.sort_by(|a, b| popularity(b).cmp(&popularity(a)).then(a.cmp(b)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+use super::{
+    Explanation, NeighborContribution, Ranking, Recommender, RecommenderError, VOTE_UNCERTAINTY,
+};
+use polars::{
+    datatypes::{PlHashMap, PlHashSet},
+    prelude::*,
+};
+use polars_lazy::prelude::*;
+use tracing::instrument;
+
+/// Number of contributing neighbors to name in each explanation
+const EXPLAINED_NEIGHBORS: usize = 3;
+
+impl Recommender {
+    /// Explain why each page in a set of recommendations made from a ranking was recommended.
+    /// Explanations are in the same order as the recommendations.
+    #[instrument(skip_all, fields(uid = ranking.uid, pages = recommendations.height()))]
+    pub fn explain(
+        &self,
+        ranking: &Ranking,
+        recommendations: &DataFrame,
+    ) -> Result<Vec<Explanation>, RecommenderError> {
+        let uid = ranking.uid;
+        let neighbors = &ranking.neighbors;
+        let neighbor_uids: Vec<&str> = neighbors
+            .column("uid")?
+            .str()?
+            .into_no_null_iter()
+            .collect();
+        let similarities: Vec<f64> = neighbors
+            .column("similarity")?
+            .f64()?
+            .into_no_null_iter()
+            .collect();
+        let neighbor_norms = neighbor_uids
+            .iter()
+            .map(|uid| self.middle_norms.get(*uid).copied())
+            .collect::<Option<Vec<f64>>>()
+            .ok_or(RecommenderError::Bounds)?;
+        let neighbor_names = self.get_usernames(&neighbor_uids)?;
+
+        let pids: Vec<u64> = recommendations
+            .column("pid")?
+            .u64()?
+            .into_no_null_iter()
+            .collect();
+        let scores = recommendations.column("weights")?.f64()?;
+
+        let mut selected = vec![col("pid")];
+        selected.extend(neighbor_uids.iter().map(|uid| col(uid)));
+        let neighbor_ratings = self
+            .rating_frame
+            .clone()
+            .filter(col("pid").is_in(lit(Series::new("pids", &pids))))
+            .select(selected)
+            .collect()?;
+
+        let rating_rows: PlHashMap<u64, usize> = neighbor_ratings
+            .column("pid")?
+            .u64()?
+            .into_no_null_iter()
+            .enumerate()
+            .map(|(row, pid)| (pid, row))
+            .collect();
+        let neighbor_columns = neighbor_uids
+            .iter()
+            .map(|uid| neighbor_ratings.column(uid)?.f64())
+            .collect::<Result<Vec<_>, PolarsError>>()?;
+
+        let liked_tags = self.get_liked_tags(uid)?;
+
+        pids.iter()
+            .zip(scores.iter())
+            .map(|(pid, score)| {
+                let row = *rating_rows.get(pid).ok_or(RecommenderError::Bounds)?;
+
+                let mut neighbor_upvotes = 0;
+                let mut neighbor_downvotes = 0;
+                let mut contributions = Vec::new();
+
+                for (i, ratings) in neighbor_columns.iter().enumerate() {
+                    let rating = ratings.get(row).unwrap_or(neighbor_norms[i]);
+
+                    if rating > neighbor_norms[i] + VOTE_UNCERTAINTY {
+                        neighbor_upvotes += 1;
+                        contributions.push((i, similarities[i] * rating));
+                    } else if rating < neighbor_norms[i] - VOTE_UNCERTAINTY {
+                        neighbor_downvotes += 1;
+                    }
+                }
+
+                contributions.sort_by(|a, b| b.1.total_cmp(&a.1));
+                let top_neighbors = contributions
+                    .iter()
+                    .take(EXPLAINED_NEIGHBORS)
+                    .map(|(i, _)| NeighborContribution {
+                        name: neighbor_names[*i].clone(),
+                        similarity: similarities[*i],
+                    })
+                    .collect();
+
+                let page = self.get_page_by_pid(*pid)?;
+                let shared_tags = match &page[3] {
+                    AnyValue::List(tags) => tags
+                        .u16()?
+                        .into_no_null_iter()
+                        .filter(|tag| liked_tags.contains(tag))
+                        .filter_map(|tag| self.get_tag_by_id(tag))
+                        // Tags starting with an underscore are for wiki bookkeeping, not content
+                        .filter(|tag| !tag.starts_with('_'))
+                        .collect(),
+                    _ => unreachable!(),
+                };
+
+                Ok(Explanation {
+                    score: score.unwrap_or(0f64),
+                    top_neighbors,
+                    neighbor_upvotes,
+                    neighbor_downvotes,
+                    shared_tags,
+                })
+            })
+            .collect()
+    }
+
+    // Get the names of each of the given users, in the same order.
+    // Users are given as uid strings, like the rating frame columns.
+    pub(super) fn get_usernames(&self, uids: &[&str]) -> Result<Vec<String>, RecommenderError> {
+        let uid_values = uids
+            .iter()
+            .map(|uid| uid.parse())
+            .collect::<Result<Vec<u64>, _>>()
+            .map_err(|_| RecommenderError::Bounds)?;
+
+        let users = self
+            .user_frame
+            .clone()
+            .lazy()
+            .filter(col("uid").is_in(lit(Series::new("uids", &uid_values))))
+            .collect()?;
+
+        let names: PlHashMap<u64, &str> = users
+            .column("uid")?
+            .u64()?
+            .into_no_null_iter()
+            .zip(users.column("name")?.str()?.into_no_null_iter())
+            .collect();
+
+        uid_values
+            .iter()
+            .map(|uid| {
+                names
+                    .get(uid)
+                    .map(|name| String::from(*name))
+                    .ok_or(RecommenderError::Bounds)
+            })
+            .collect()
+    }
+
+    // Get every tag on a page the given user has upvoted
+    fn get_liked_tags(&self, uid: u64) -> Result<PlHashSet<u16>, RecommenderError> {
+        let uid_str = format!("{}", uid);
+        let uid_unvote = match self.middle_norms.get(&uid_str) {
+            Some(value) => *value,
+            None => return Err(RecommenderError::UserNotFound),
+        };
+
+        let liked_pages = self
+            .rating_frame
+            .clone()
+            .filter(col(&uid_str).gt(lit(uid_unvote + VOTE_UNCERTAINTY)))
+            .select([col("pid")])
+            .collect()?;
+
+        let mut liked_tags = PlHashSet::new();
+        for pid in liked_pages.column("pid")?.u64()?.into_no_null_iter() {
+            let page = self.get_page_by_pid(pid)?;
+            match &page[3] {
+                AnyValue::List(tags) => liked_tags.extend(tags.u16()?.into_no_null_iter()),
+                _ => unreachable!(),
+            }
+        }
+
+        Ok(liked_tags)
+    }
+}
@@ -0,0 +1,83 @@
+use super::{GroupStrategy, Ranking, Recommender, RecommenderError};
+use polars::{datatypes::PlHashMap, prelude::*};
+use polars_lazy::prelude::*;
+use tracing::instrument;
+
+impl Recommender {
+    /// Return every page none of a group of users has voted on, ordered by how highly the group
+    /// as a whole is recommended it. Each member's weights are combined with the given strategy.
+    #[instrument(skip(self))]
+    pub fn get_recommendations_for_group(
+        &self,
+        uids: &[u64],
+        strategy: GroupStrategy,
+    ) -> Result<DataFrame, RecommenderError> {
+        let rankings = uids
+            .iter()
+            .map(|uid| self.get_ranking_by_uid(*uid))
+            .collect::<Result<Vec<Ranking>, RecommenderError>>()?;
+
+        self.combine_rankings(&rankings.iter().collect::<Vec<&Ranking>>(), strategy)
+    }
+
+    /// Combine the rankings of each member of a group into one set of recommendations, with pid
+    /// and weights columns, in descending order of weight.
+    /// Only pages which are in every ranking are kept, so nothing any member has voted on is
+    /// recommended. Weights are scaled so that each member's strongest opinion is 1 or -1 before
+    /// they are combined, so that members whose neighbors agree more do not outweigh the rest.
+    pub fn combine_rankings(
+        &self,
+        rankings: &[&Ranking],
+        strategy: GroupStrategy,
+    ) -> Result<DataFrame, RecommenderError> {
+        // Combined score and the number of rankings each page was in
+        let mut scores: PlHashMap<u64, (f64, usize)> = PlHashMap::new();
+
+        for ranking in rankings {
+            let pids = ranking.candidates.column("pid")?.u64()?;
+            let weights = ranking.candidates.column("weights")?.f64()?;
+
+            let strongest = weights
+                .into_no_null_iter()
+                .fold(0f64, |strongest, weight| strongest.max(weight.abs()));
+            let scale = if strongest > 0f64 { strongest } else { 1f64 };
+
+            for (pid, weight) in pids.into_no_null_iter().zip(weights.into_no_null_iter()) {
+                let weight = weight / scale;
+                scores
+                    .entry(pid)
+                    .and_modify(|(score, count)| {
+                        *score = match strategy {
+                            GroupStrategy::Average => *score + weight,
+                            GroupStrategy::LeastMisery => score.min(weight),
+                            GroupStrategy::MostPleasure => score.max(weight),
+                        };
+                        *count += 1;
+                    })
+                    .or_insert((weight, 1));
+            }
+        }
+
+        let members = rankings.len();
+        let (pids, weights): (Vec<u64>, Vec<f64>) = scores
+            .into_iter()
+            .filter(|(_, (_, count))| *count == members)
+            .map(|(pid, (score, _))| match strategy {
+                GroupStrategy::Average => (pid, score / members as f64),
+                _ => (pid, score),
+            })
+            .unzip();
+
+        Ok(DataFrame::new(vec![
+            Series::new("pid", pids),
+            Series::new("weights", weights),
+        ])?
+        .lazy()
+        // Pages are gathered in no particular order, so ties are broken by pid to keep it stable
+        .sort(
+            ["weights", "pid"],
+            SortMultipleOptions::new().with_order_descending_multi([true, false]),
+        )
+        .collect()?)
+    }
+}
@@ -0,0 +1,301 @@
+use super::{user_search::fold, RecommenderError};
+use fst::{automaton::Str, Automaton, IntoStreamer, Map, MapBuilder, Streamer};
+use polars::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+
+/// Most words looked at for each search term, so that very short terms stay fast
+const MAX_SCANNED_WORDS: usize = 1000;
+
+/// Where in a page a word was found, as bits so that one word can be in several places
+const IN_NAME: u8 = 1;
+const IN_URL: u8 = 2;
+const IN_TAGS: u8 = 4;
+
+/// Index of the words in every page's name, url, and tags.
+/// Names include the titles the scraper finds on the ListPages, like "The Shy Guy".
+pub struct PageIndex {
+    /// Maps each folded word to its position in `postings`
+    words: Map<Vec<u8>>,
+    /// Rows of the page frame with each word, and where in the page it was found
+    postings: Vec<Vec<(u32, u8)>>,
+    pids: Vec<u64>,
+    /// Vote counts, used to put popular pages first when matches are equally good
+    votes: Vec<u32>,
+}
+
+impl PageIndex {
+    /// Build the index from a page frame with name, url, pid, tags, and votes columns,
+    /// and the tags frame which names each tag id
+    pub fn new(
+        page_frame: &DataFrame,
+        tags_frame: &DataFrame,
+    ) -> Result<PageIndex, RecommenderError> {
+        let tags: Vec<&str> = tags_frame
+            .column("tag")?
+            .str()?
+            .into_no_null_iter()
+            .collect();
+        let names = page_frame.column("name")?.str()?;
+        let urls = page_frame.column("url")?.str()?;
+        let tag_lists = page_frame.column("tags")?.list()?;
+
+        // Words must be inserted in order, so they are gathered and sorted first
+        let mut entries: BTreeMap<String, BTreeMap<u32, u8>> = BTreeMap::new();
+        let mut add = |text: &str, row: u32, field: u8| {
+            for word in words(text) {
+                *entries.entry(word).or_default().entry(row).or_default() |= field;
+            }
+        };
+
+        for (row, ((name, url), page_tags)) in names
+            .into_no_null_iter()
+            .zip(urls.into_no_null_iter())
+            .zip(tag_lists.into_no_null_iter())
+            .enumerate()
+        {
+            let row = row as u32;
+
+            add(name, row, IN_NAME);
+            add(url, row, IN_URL);
+            // The whole url as one word, so that "scp096" finds "scp-096"
+            add(&url.replace('-', ""), row, IN_URL);
+
+            for tag in page_tags.u16()?.into_no_null_iter() {
+                if let Some(tag) = tags.get(tag as usize) {
+                    add(tag, row, IN_TAGS);
+                }
+            }
+        }
+
+        let mut builder = MapBuilder::memory();
+        let mut postings = Vec::with_capacity(entries.len());
+        for (word, rows) in entries {
+            builder
+                .insert(word, postings.len() as u64)
+                .expect("Words are sorted and unique");
+            postings.push(rows.into_iter().collect());
+        }
+
+        PageIndex::from_parts(page_frame, builder.into_map(), postings)
+    }
+
+    /// Load an index saved by `to_frames`, given the same page frame it was built from
+    pub fn from_frames(
+        page_frame: &DataFrame,
+        words: &DataFrame,
+        postings: &DataFrame,
+    ) -> Result<PageIndex, RecommenderError> {
+        let corrupt =
+            || RecommenderError::Snapshot(String::from("page index does not match the pages"));
+
+        let words = words
+            .column("words")?
+            .binary()?
+            .get(0)
+            .ok_or_else(corrupt)?;
+        let words = Map::new(words.to_vec()).map_err(|_| corrupt())?;
+
+        let postings = postings
+            .column("rows")?
+            .list()?
+            .into_no_null_iter()
+            .zip(postings.column("fields")?.list()?.into_no_null_iter())
+            .map(|(rows, fields)| {
+                Ok(rows
+                    .u32()?
+                    .into_no_null_iter()
+                    .zip(fields.u8()?.into_no_null_iter())
+                    .collect())
+            })
+            .collect::<Result<Vec<Vec<(u32, u8)>>, PolarsError>>()?;
+
+        // Words are numbered in order, so every number is a posting if the counts match
+        let page_count = page_frame.height() as u32;
+        if words.len() != postings.len()
+            || postings.iter().flatten().any(|(row, _)| *row >= page_count)
+        {
+            return Err(corrupt());
+        }
+
+        PageIndex::from_parts(page_frame, words, postings)
+    }
+
+    /// The index as frames, so that snapshots do not need to rebuild it.
+    /// These are the word map as a single value of bytes, and the rows and places of each word.
+    pub fn to_frames(&self) -> Result<(DataFrame, DataFrame), RecommenderError> {
+        let words = DataFrame::new(vec![Series::new("words", [self.words.as_fst().as_bytes()])])?;
+
+        let (rows, fields): (Vec<Series>, Vec<Series>) = self
+            .postings
+            .iter()
+            .map(|posting| {
+                let (rows, fields): (Vec<u32>, Vec<u8>) = posting.iter().copied().unzip();
+                (Series::new("", rows), Series::new("", fields))
+            })
+            .unzip();
+        let postings = DataFrame::new(vec![
+            Series::new("rows", rows),
+            Series::new("fields", fields),
+        ])?;
+
+        Ok((words, postings))
+    }
+
+    // Finish an index from its word map and postings
+    fn from_parts(
+        page_frame: &DataFrame,
+        words: Map<Vec<u8>>,
+        postings: Vec<Vec<(u32, u8)>>,
+    ) -> Result<PageIndex, RecommenderError> {
+        Ok(PageIndex {
+            words,
+            postings,
+            pids: page_frame
+                .column("pid")?
+                .u64()?
+                .into_no_null_iter()
+                .collect(),
+            votes: page_frame
+                .column("votes")?
+                .u32()?
+                .into_no_null_iter()
+                .collect(),
+        })
+    }
+
+    /// Find the pids of the pages best matching a query, best first.
+    /// Every word in the query must start a word in the page's name, url, or tags.
+    /// Whole words count for more than the starts of words, and names count for more than
+    /// urls, which count for more than tags.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<u64> {
+        let terms = words(query);
+        if terms.is_empty() || limit == 0 {
+            return Vec::new();
+        }
+
+        let mut scores: Option<HashMap<u32, u32>> = None;
+        for term in &terms {
+            let term_scores = self.score_term(term);
+
+            scores = Some(match scores {
+                None => term_scores,
+                Some(mut scores) => {
+                    scores.retain(|row, _| term_scores.contains_key(row));
+                    for (row, score) in scores.iter_mut() {
+                        *score += term_scores[row];
+                    }
+                    scores
+                }
+            });
+        }
+
+        let mut scores = scores.unwrap_or_default();
+
+        // Searches like "scp-17", with words joined by punctuation rather than spaces, are often
+        // the start of a url. Whole urls are also indexed as one word, and a match with one
+        // counts as much as matching every term separately
+        if terms.len() > 1 && !query.trim().contains(char::is_whitespace) {
+            for (row, score) in self.score_term(&fold(query)) {
+                *scores.entry(row).or_insert(0) += score * terms.len() as u32;
+            }
+        }
+
+        let mut found: Vec<(u32, u32)> = scores.into_iter().collect();
+        found.sort_unstable_by(|(row_a, score_a), (row_b, score_b)| {
+            score_b
+                .cmp(score_a)
+                .then(self.votes[*row_b as usize].cmp(&self.votes[*row_a as usize]))
+                .then(row_a.cmp(row_b))
+        });
+
+        found
+            .into_iter()
+            .take(limit)
+            .map(|(row, _)| self.pids[row as usize])
+            .collect()
+    }
+
+    // Score every page with a word starting with the term, keeping each page's best match
+    fn score_term(&self, term: &str) -> HashMap<u32, u32> {
+        let mut scores = HashMap::new();
+        let mut stream = self
+            .words
+            .search(Str::new(term).starts_with())
+            .into_stream();
+        let mut scanned = 0;
+
+        while let Some((word, posting)) = stream.next() {
+            let whole_word = word == term.as_bytes();
+
+            for (row, fields) in &self.postings[posting as usize] {
+                let score = field_score(*fields) * if whole_word { 2 } else { 1 };
+                let best = scores.entry(*row).or_insert(0);
+                *best = score.max(*best);
+            }
+
+            scanned += 1;
+            if scanned == MAX_SCANNED_WORDS {
+                break;
+            }
+        }
+
+        scores
+    }
+}
+
+// How much a match counts for, based on the best place it was found
+fn field_score(fields: u8) -> u32 {
+    if fields & IN_NAME != 0 {
+        3
+    } else if fields & IN_URL != 0 {
+        2
+    } else {
+        1
+    }
+}
+
+// Split text into folded words, without repeats
+fn words(text: &str) -> Vec<String> {
+    let mut words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .map(fold)
+        .filter(|word| !word.is_empty())
+        .collect();
+    words.sort_unstable();
+    words.dedup();
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_pages() {
+        let tags = df!("tag" => ["keter", "euclid", "tale"]).expect("Tags not made");
+        let tag_lists = [
+            Series::new("", [0u16]),
+            Series::new("", [1u16]),
+            Series::new("", [2u16]),
+        ];
+        let frame = df!(
+            "name" => ["SCP-096 - The \"Shy Guy\"", "SCP-173 - The Sculpture", "Shy Guys"],
+            "url" => ["scp-096", "scp-173", "shy-guys"],
+            "pid" => [1u64, 2, 3],
+            "tags" => tag_lists,
+            "votes" => [500u32, 900, 10],
+        )
+        .expect("Frame not made");
+        let index = PageIndex::new(&frame, &tags).expect("Index not made");
+
+        // Subtitles can be searched, and whole words beat the starts of words
+        assert_eq!(index.search("shy guy", 10), vec![1, 3]);
+        assert_eq!(index.search("scp096", 10), vec![1]);
+        assert_eq!(index.search("scp-09", 10), vec![1]);
+        // Equal matches put popular pages first
+        assert_eq!(index.search("scp", 10), vec![2, 1]);
+        assert_eq!(index.search("the scul", 10), vec![2]);
+        assert_eq!(index.search("tale", 10), vec![3]);
+        assert!(index.search("keter sculpture", 10).is_empty());
+    }
+}
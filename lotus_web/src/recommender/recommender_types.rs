@@ -1,11 +1,20 @@
-use lotus::OutputFiles;
+use lotus::{config, OutputFiles};
 use polars::prelude::*;
-use std::{fmt::Debug, io};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Debug, io, time::Duration};
 
 pub enum RecommenderError {
     Polars(PolarsError),
     File(io::Error),
     Bounds,
+    /// A snapshot file could not be used, with the reason why
+    Snapshot(String),
+    /// The requested user is not in the recommender
+    UserNotFound,
+    /// The requested page is not in the recommender
+    PageNotFound,
+    /// A tag filter could not be understood, with the reason why
+    TagFilter(String),
 }
 
 impl Debug for RecommenderError {
@@ -14,6 +23,10 @@ impl Debug for RecommenderError {
             Self::Polars(err) => format!("Polars: {:?}", err),
             Self::File(err) => format!("File: {:?}", err),
             Self::Bounds => String::from("OOB somewhere"),
+            Self::Snapshot(reason) => format!("Snapshot: {}", reason),
+            Self::UserNotFound => String::from("User not found"),
+            Self::PageNotFound => String::from("Page not found"),
+            Self::TagFilter(reason) => format!("Tag filter: {}", reason),
         };
 
         write!(f, "{}", message)
@@ -32,6 +45,107 @@ impl From<io::Error> for RecommenderError {
     }
 }
 
+/// Reasons why a page was recommended to a user
+#[derive(Clone, Debug, Serialize)]
+pub struct Explanation {
+    /// The weight the page was ranked by
+    pub score: f64,
+    /// The similar users who added the most weight to the page, most weight first
+    pub top_neighbors: Vec<NeighborContribution>,
+    /// The number of similar users who upvoted the page
+    pub neighbor_upvotes: u32,
+    /// The number of similar users who downvoted the page
+    pub neighbor_downvotes: u32,
+    /// Tags the page shares with pages the user has upvoted
+    pub shared_tags: Vec<String>,
+}
+
+/// A similar user who contributed to a recommendation
+#[derive(Clone, Debug, Serialize)]
+pub struct NeighborContribution {
+    pub name: String,
+    pub similarity: f64,
+}
+
+/// How the scores each member of a group would give a page are combined into one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupStrategy {
+    /// The average of every member's score
+    #[default]
+    Average,
+    /// The lowest score of any member, so that nobody is left reading something they dislike
+    LeastMisery,
+    /// The highest score of any member, so that somebody is sure to love it
+    MostPleasure,
+}
+
+/// What the recommender knows about a user's taste
+#[derive(Clone, Debug, Serialize)]
+pub struct TasteProfile {
+    pub name: String,
+    pub uid: u64,
+    pub votes: u32,
+    pub upvotes: u32,
+    pub downvotes: u32,
+    /// The fraction of the user's votes which are upvotes
+    pub upvote_ratio: f64,
+    /// The tags the user likes most, most liked first
+    pub top_tags: Vec<TagAffinity>,
+    /// The users who vote most like this user, most similar first
+    pub similar_users: Vec<NeighborContribution>,
+    /// Tags the user votes on more than the wiki does, most different first
+    pub more_than_average: Vec<TagAffinity>,
+    /// Tags the user votes on less than the wiki does, most different first
+    pub less_than_average: Vec<TagAffinity>,
+    /// The average number of votes on the pages the user voted on.
+    /// Lower than the wiki's average means the user reads less popular pages
+    pub average_page_votes: f64,
+    /// The average number of votes on the page of any vote on the wiki
+    pub wiki_average_page_votes: f64,
+}
+
+/// How the taste of two users compares. Pages are given by pid
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    /// The name of the first user
+    pub a: String,
+    /// The name of the second user
+    pub b: String,
+    /// How similar the recommender thinks the users are, from -1 to 1
+    pub similarity: f64,
+    /// The number of pages both users voted the same way on
+    pub agreements: u32,
+    /// The number of pages the users voted differently on
+    pub disagreements: u32,
+    pub both_liked: Vec<u64>,
+    pub both_disliked: Vec<u64>,
+    pub a_liked_b_disliked: Vec<u64>,
+    pub b_liked_a_disliked: Vec<u64>,
+    /// Tags both users like, the most liked by both first
+    pub shared_tags: Vec<String>,
+    /// Pages the first user upvoted which the second has not voted on
+    pub liked_by_a_unread_by_b: Vec<u64>,
+    /// Pages the second user upvoted which the first has not voted on
+    pub liked_by_b_unread_by_a: Vec<u64>,
+}
+
+/// How much a user likes a tag
+#[derive(Clone, Debug, Serialize)]
+pub struct TagAffinity {
+    pub tag: String,
+    /// The user's upvotes on pages with the tag
+    pub upvotes: u32,
+    /// The user's downvotes on pages with the tag
+    pub downvotes: u32,
+    /// The fraction of the user's votes which are on pages with the tag
+    pub share: f64,
+    /// The fraction of every vote on the wiki which is on pages with the tag
+    pub wiki_share: f64,
+    /// Upvotes minus downvotes on pages with the tag, as a fraction of all of the user's votes
+    pub affinity: f64,
+}
+
 #[derive(Clone, Debug)]
 pub struct RecommenderOptions {
     /// Minimum number of votes to consider a users opinion
@@ -43,6 +157,18 @@ pub struct RecommenderOptions {
     /// Setting this higher gives a wider variety of opinions, but makes
     /// suggestions more susceptible to popularity bias
     users_to_consider: u32,
+    /// How much to discount pages by their popularity when nothing else is requested
+    /// This is used as an exponent on the number of votes each page has, so 0 disables it and
+    /// 1 divides page weights by their vote count
+    novelty: f64,
+    /// Value given to an upvote before ratings are normalized
+    upvote_weight: f64,
+    /// Value given to a downvote before ratings are normalized, as a positive number
+    /// Setting this apart from the upvote weight changes how much dislikes count towards both
+    /// user similarity and page weights
+    downvote_weight: f64,
+    /// Whether to remove pages which most of the similar users who voted on them downvoted
+    downvote_exclusion: bool,
     /// Locations of the output files
     outputs: OutputFiles,
 }
@@ -51,12 +177,21 @@ impl RecommenderOptions {
     /// Create an options instance with the default options
     pub fn new() -> RecommenderOptions {
         RecommenderOptions {
-            min_votes: 10,
-            users_to_consider: 30,
+            min_votes: config::DEFAULT_MIN_VOTES,
+            users_to_consider: config::DEFAULT_USERS_TO_CONSIDER,
+            novelty: config::DEFAULT_NOVELTY,
+            upvote_weight: config::DEFAULT_UPVOTE_WEIGHT,
+            downvote_weight: config::DEFAULT_DOWNVOTE_WEIGHT,
+            downvote_exclusion: false,
             outputs: OutputFiles::new(),
         }
     }
 
+    pub fn with_outputs(mut self, new_outputs: OutputFiles) -> RecommenderOptions {
+        self.outputs = new_outputs;
+        self
+    }
+
     pub fn with_articles_file(mut self, new_articles_file: String) -> RecommenderOptions {
         self.outputs.article_output = new_articles_file;
         self
@@ -75,6 +210,33 @@ impl RecommenderOptions {
         self.min_votes
     }
 
+    pub fn with_downvote_exclusion(mut self, new_downvote_exclusion: bool) -> RecommenderOptions {
+        self.downvote_exclusion = new_downvote_exclusion;
+        self
+    }
+
+    pub fn get_downvote_exclusion(&self) -> bool {
+        self.downvote_exclusion
+    }
+
+    pub fn with_downvote_weight(mut self, new_downvote_weight: f64) -> RecommenderOptions {
+        self.downvote_weight = new_downvote_weight;
+        self
+    }
+
+    pub fn get_downvote_weight(&self) -> f64 {
+        self.downvote_weight
+    }
+
+    pub fn with_novelty(mut self, new_novelty: f64) -> RecommenderOptions {
+        self.novelty = new_novelty;
+        self
+    }
+
+    pub fn get_novelty(&self) -> f64 {
+        self.novelty
+    }
+
     pub fn with_tags_file(mut self, new_tags_file: String) -> RecommenderOptions {
         self.outputs.tags_output = new_tags_file;
         self
@@ -84,6 +246,15 @@ impl RecommenderOptions {
         &self.outputs.tags_output
     }
 
+    pub fn with_upvote_weight(mut self, new_upvote_weight: f64) -> RecommenderOptions {
+        self.upvote_weight = new_upvote_weight;
+        self
+    }
+
+    pub fn get_upvote_weight(&self) -> f64 {
+        self.upvote_weight
+    }
+
     pub fn with_users_to_consider(mut self, new_users_to_consider: u32) -> RecommenderOptions {
         self.users_to_consider = new_users_to_consider;
         self
@@ -110,6 +281,28 @@ impl RecommenderOptions {
     pub fn get_votes_file(&self) -> &String {
         &self.outputs.votes_output
     }
+
+    /// Returns a hash of every option which changes the built model.
+    /// Snapshots record this so that they are not loaded with options they were not built with.
+    pub fn get_model_hash(&self) -> u64 {
+        let mut model_options = Vec::new();
+        model_options.extend(self.min_votes.to_le_bytes());
+        model_options.extend(self.upvote_weight.to_le_bytes());
+        model_options.extend(self.downvote_weight.to_le_bytes());
+
+        fnv_hash(&model_options)
+    }
+
+    /// Returns a hash of every option which changes the ranking given to a user.
+    /// This covers the model options as well as the options used while ranking.
+    pub fn get_engine_hash(&self) -> u64 {
+        let mut engine_options = Vec::new();
+        engine_options.extend(self.get_model_hash().to_le_bytes());
+        engine_options.extend(self.users_to_consider.to_le_bytes());
+        engine_options.push(self.downvote_exclusion as u8);
+
+        fnv_hash(&engine_options)
+    }
 }
 
 impl Default for RecommenderOptions {
@@ -117,3 +310,91 @@ impl Default for RecommenderOptions {
         Self::new()
     }
 }
+
+/// Every page a user could be recommended, ranked before any per-request filters are applied.
+/// Rankings are the expensive part of a recommendation, so they can be kept and filtered again
+/// with `Recommender::filter_ranking`.
+#[derive(Clone)]
+pub struct Ranking {
+    /// The user this ranking is for
+    pub(super) uid: u64,
+    /// The most similar users to this user, as given by `Recommender::get_neighbors`
+    pub(super) neighbors: DataFrame,
+    /// Every page the user has not voted on, with weights, in descending order of weight
+    pub(super) candidates: DataFrame,
+    /// Time spent finding similar users
+    pub(super) similarity_time: Duration,
+    /// Time spent weighting pages by the opinions of similar users
+    pub(super) weighting_time: Duration,
+}
+
+impl Ranking {
+    pub fn get_uid(&self) -> u64 {
+        self.uid
+    }
+
+    pub fn get_neighbors(&self) -> &DataFrame {
+        &self.neighbors
+    }
+
+    /// Returns the number of pages in the ranking
+    pub fn len(&self) -> usize {
+        self.candidates.height()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.height() == 0
+    }
+
+    pub fn get_similarity_time(&self) -> Duration {
+        self.similarity_time
+    }
+
+    pub fn get_weighting_time(&self) -> Duration {
+        self.weighting_time
+    }
+}
+
+#[cfg(test)]
+impl Ranking {
+    /// A ranking of no pages, for testing code which only keeps rankings
+    pub fn empty(uid: u64) -> Ranking {
+        Ranking {
+            uid,
+            neighbors: DataFrame::empty(),
+            candidates: DataFrame::empty(),
+            similarity_time: Duration::ZERO,
+            weighting_time: Duration::ZERO,
+        }
+    }
+}
+
+/// A rating a user gave a page in LOTUS, rather than by voting on the wiki
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LocalRating {
+    pub pid: u64,
+    /// Whether the page was liked or disliked
+    pub upvote: bool,
+}
+
+/// How much data a recommender was built from
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ModelSize {
+    /// Users with enough votes to be recommended to
+    pub users: usize,
+    pub pages: usize,
+    /// Every vote on every page, including those from users with too few votes
+    pub votes: u64,
+    pub tags: usize,
+}
+
+// FNV-1a, since the std hashers are not guaranteed to be stable between releases
+fn fnv_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
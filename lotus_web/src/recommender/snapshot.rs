@@ -0,0 +1,290 @@
+use super::{PageIndex, Recommender, RecommenderError, RecommenderOptions, TagIndex, UserIndex};
+use polars::{datatypes::PlHashMap, prelude::*};
+use polars_lazy::prelude::*;
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Cursor, Write},
+};
+
+/// Bytes every snapshot file starts with
+const SNAPSHOT_MAGIC: &[u8; 8] = b"LOTUSSNP";
+
+/// Version of the snapshot layout.
+/// This must be bumped whenever the layout or the contents of any section changes.
+const SNAPSHOT_VERSION: u32 = 4;
+
+/// Number of frames stored in a snapshot.
+/// In order: ratings, middle norms, pages, page map, tags, users, page words, page postings,
+/// user keys, user groups, and tag pages. The last five are the search and tag indexes, which are
+/// saved since building them is most of the work left once the frames are processed.
+const SECTION_COUNT: u32 = 11;
+
+/// Length of the header: magic, version, model hash, scrape time, and section count
+const HEADER_LENGTH: usize = 8 + 4 + 8 + 8 + 4;
+
+/// Scrape time saved for data without one, which no real scrape can have
+const UNKNOWN_SCRAPE_TIME: u64 = 0;
+
+impl Recommender {
+    /// Creates a recommender from a snapshot made by `save_snapshot`.
+    /// This skips all of the frame processing and index building done by `new_with_options`, so
+    /// it is far faster. The snapshot must have been built with the same model options as the
+    /// ones given.
+    pub fn new_from_snapshot(
+        snapshot_file: &str,
+        options: &RecommenderOptions,
+    ) -> Result<Recommender, RecommenderError> {
+        // Every section is copied into its own frame, so the file is not needed after this
+        let bytes = fs::read(snapshot_file)?;
+
+        let (scraped_at, frames) = read_sections(&bytes, options.get_model_hash())?;
+        let mut frames = frames.into_iter();
+        let mut next_frame = || {
+            frames
+                .next()
+                .expect("Section count was checked while reading")
+        };
+
+        let rating_frame = next_frame();
+        let middle_norms = middle_norms_from_frame(&next_frame())?;
+        let page_frame = next_frame();
+        let page_map = page_map_from_frame(&next_frame())?;
+        let tags_frame = next_frame();
+        let user_frame = next_frame();
+        let page_words = next_frame();
+        let page_postings = next_frame();
+        let user_keys = next_frame();
+        let user_groups = next_frame();
+        let tag_pages = next_frame();
+
+        if page_map.len() != page_frame.height() {
+            return Err(RecommenderError::Snapshot(String::from(
+                "page map does not match the page frame",
+            )));
+        }
+
+        Ok(Recommender {
+            middle_norms,
+            page_index: PageIndex::from_frames(&page_frame, &page_words, &page_postings)?,
+            tag_index: TagIndex::from_frame(&page_frame, &tags_frame, &tag_pages)?,
+            page_frame,
+            page_map,
+            rating_frame: rating_frame.lazy(),
+            tags_frame,
+            user_index: UserIndex::from_frames(&user_frame, &user_keys, &user_groups)?,
+            user_frame,
+            users_to_consider: options.get_users_to_consider(),
+            novelty: options.get_novelty(),
+            upvote_weight: options.get_upvote_weight(),
+            downvote_weight: options.get_downvote_weight(),
+            downvote_exclusion: options.get_downvote_exclusion(),
+            engine_hash: options.get_engine_hash(),
+            scraped_at,
+        })
+    }
+
+    /// Writes everything needed to recreate this recommender to a single snapshot file.
+    /// The model hash of the given options is stored with it, which should be the options this
+    /// recommender was created with.
+    pub fn save_snapshot(
+        &self,
+        snapshot_file: &str,
+        options: &RecommenderOptions,
+    ) -> Result<(), RecommenderError> {
+        let (page_words, page_postings) = self.page_index.to_frames()?;
+        let (user_keys, user_groups) = self.user_index.to_frames()?;
+        let mut sections = [
+            self.rating_frame.clone().collect()?,
+            self.middle_norms_frame()?,
+            self.page_frame.clone(),
+            self.page_map_frame()?,
+            self.tags_frame.clone(),
+            self.user_frame.clone(),
+            page_words,
+            page_postings,
+            user_keys,
+            user_groups,
+            self.tag_index.to_frame()?,
+        ];
+
+        let mut writer = BufWriter::new(File::create(snapshot_file)?);
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        writer.write_all(&options.get_model_hash().to_le_bytes())?;
+        writer.write_all(&self.scraped_at.unwrap_or(UNKNOWN_SCRAPE_TIME).to_le_bytes())?;
+        writer.write_all(&SECTION_COUNT.to_le_bytes())?;
+
+        for frame in sections.iter_mut() {
+            // Uncompressed, since the point of a snapshot is to be read as fast as possible
+            let mut buffer = Vec::new();
+            IpcWriter::new(&mut buffer).finish(frame)?;
+
+            writer.write_all(&(buffer.len() as u64).to_le_bytes())?;
+            writer.write_all(&buffer)?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    // The middle norms as a frame, with a uid string column and a norm column
+    fn middle_norms_frame(&self) -> Result<DataFrame, RecommenderError> {
+        let (uids, norms): (Vec<&str>, Vec<f64>) = self
+            .middle_norms
+            .iter()
+            .map(|(uid, norm)| (uid.as_str(), *norm))
+            .unzip();
+
+        Ok(DataFrame::new(vec![
+            Series::new("uid", uids),
+            Series::new("norm", norms),
+        ])?)
+    }
+
+    // The page map as a frame, with a pid column and a page frame index column
+    fn page_map_frame(&self) -> Result<DataFrame, RecommenderError> {
+        let (pids, indices): (Vec<u64>, Vec<u64>) = self
+            .page_map
+            .iter()
+            .map(|(pid, index)| (*pid, *index as u64))
+            .unzip();
+
+        Ok(DataFrame::new(vec![
+            Series::new("pid", pids),
+            Series::new("index", indices),
+        ])?)
+    }
+}
+
+// Check the header of a snapshot and read every section in it, along with the scrape time
+fn read_sections(
+    bytes: &[u8],
+    model_hash: u64,
+) -> Result<(Option<u64>, Vec<DataFrame>), RecommenderError> {
+    if bytes.len() < HEADER_LENGTH || &bytes[0..8] != SNAPSHOT_MAGIC {
+        return Err(RecommenderError::Snapshot(String::from(
+            "file is not a LOTUS snapshot",
+        )));
+    }
+
+    let version = u32::from_le_bytes(bytes[8..12].try_into().expect("Slice is 4 bytes"));
+    if version != SNAPSHOT_VERSION {
+        return Err(RecommenderError::Snapshot(format!(
+            "snapshot is version {}, but version {} is required",
+            version, SNAPSHOT_VERSION
+        )));
+    }
+
+    let hash = u64::from_le_bytes(bytes[12..20].try_into().expect("Slice is 8 bytes"));
+    if hash != model_hash {
+        return Err(RecommenderError::Snapshot(String::from(
+            "snapshot was built with different model options",
+        )));
+    }
+
+    let scraped_at = u64::from_le_bytes(bytes[20..28].try_into().expect("Slice is 8 bytes"));
+    let scraped_at = (scraped_at != UNKNOWN_SCRAPE_TIME).then_some(scraped_at);
+
+    let count = u32::from_le_bytes(bytes[28..32].try_into().expect("Slice is 4 bytes"));
+    if count != SECTION_COUNT {
+        return Err(RecommenderError::Snapshot(format!(
+            "snapshot has {} sections instead of {}",
+            count, SECTION_COUNT
+        )));
+    }
+
+    let mut frames = Vec::with_capacity(SECTION_COUNT as usize);
+    let mut position = HEADER_LENGTH;
+    for _ in 0..SECTION_COUNT {
+        let section = match read_section(bytes, &mut position) {
+            Some(section) => section,
+            None => {
+                return Err(RecommenderError::Snapshot(String::from(
+                    "snapshot is truncated",
+                )))
+            }
+        };
+
+        frames.push(IpcReader::new(Cursor::new(section)).finish()?);
+    }
+
+    Ok((scraped_at, frames))
+}
+
+// Read the section at the given position, which is its length as 8 bytes and then its contents,
+// and move the position past it. Lengths come from the file, so a corrupt one can be anything;
+// None is returned if it goes past the end of the file.
+fn read_section<'a>(bytes: &'a [u8], position: &mut usize) -> Option<&'a [u8]> {
+    let start = position.checked_add(8)?;
+    let length = u64::from_le_bytes(bytes.get(*position..start)?.try_into().ok()?);
+    let end = start.checked_add(usize::try_from(length).ok()?)?;
+
+    let section = bytes.get(start..end)?;
+    *position = end;
+
+    Some(section)
+}
+
+fn middle_norms_from_frame(frame: &DataFrame) -> Result<PlHashMap<String, f64>, RecommenderError> {
+    let uids = frame.column("uid")?.str()?;
+    let norms = frame.column("norm")?.f64()?;
+
+    Ok(uids
+        .into_no_null_iter()
+        .zip(norms.into_no_null_iter())
+        .map(|(uid, norm)| (String::from(uid), norm))
+        .collect())
+}
+
+fn page_map_from_frame(frame: &DataFrame) -> Result<PlHashMap<u64, usize>, RecommenderError> {
+    let pids = frame.column("pid")?.u64()?;
+    let indices = frame.column("index")?.u64()?;
+
+    Ok(pids
+        .into_no_null_iter()
+        .zip(indices.into_no_null_iter())
+        .map(|(pid, index)| (pid, index as usize))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reject_non_snapshot() {
+        let result = read_sections(b"PAR1 this is not a snapshot at all", 0);
+        assert!(matches!(result, Err(RecommenderError::Snapshot(_))));
+    }
+
+    #[test]
+    fn reject_other_options() {
+        let options = RecommenderOptions::new();
+        let other_options = RecommenderOptions::new().with_min_votes(100);
+        assert_ne!(options.get_model_hash(), other_options.get_model_hash());
+
+        let mut bytes = Vec::from(*SNAPSHOT_MAGIC);
+        bytes.extend(SNAPSHOT_VERSION.to_le_bytes());
+        bytes.extend(options.get_model_hash().to_le_bytes());
+        bytes.extend(UNKNOWN_SCRAPE_TIME.to_le_bytes());
+        bytes.extend(SECTION_COUNT.to_le_bytes());
+
+        let result = read_sections(&bytes, other_options.get_model_hash());
+        assert!(matches!(result, Err(RecommenderError::Snapshot(_))));
+
+        // Same options get past the header, but there is nothing after it
+        let result = read_sections(&bytes, options.get_model_hash());
+        assert!(matches!(result, Err(RecommenderError::Snapshot(_))));
+
+        // Corrupt lengths are reported rather than overflowing
+        for length in [u64::MAX, u64::MAX - 7, 1 << 40] {
+            let mut corrupt = bytes.clone();
+            corrupt.extend(length.to_le_bytes());
+            corrupt.extend([0u8; 16]);
+
+            let result = read_sections(&corrupt, options.get_model_hash());
+            assert!(matches!(result, Err(RecommenderError::Snapshot(_))));
+        }
+    }
+}
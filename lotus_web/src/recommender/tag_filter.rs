@@ -0,0 +1,386 @@
+use super::RecommenderError;
+use polars::{datatypes::PlHashMap, prelude::*};
+use roaring::RoaringBitmap;
+use std::{iter::Peekable, vec::IntoIter};
+
+/// Most tags, keywords, and parentheses a tag filter expression can have
+const MAX_FILTER_TOKENS: usize = 256;
+
+/// Most parentheses and NOTs a tag filter expression can have inside each other, since each one
+/// is parsed and matched with another recursive call
+const MAX_FILTER_DEPTH: usize = 32;
+
+/// A condition on the tags of a page, like `scp AND (horror OR cosmic) AND NOT joke`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TagFilter {
+    /// The page has this tag id
+    Tag(u16),
+    /// Every one of these is true. With none, this is true for every page
+    All(Vec<TagFilter>),
+    /// At least one of these is true
+    Any(Vec<TagFilter>),
+    Not(Box<TagFilter>),
+}
+
+impl Default for TagFilter {
+    /// A filter which every page passes
+    fn default() -> Self {
+        TagFilter::All(Vec::new())
+    }
+}
+
+impl TagFilter {
+    /// Whether every page passes this filter without it needing to be checked
+    pub fn is_empty(&self) -> bool {
+        matches!(self, TagFilter::All(filters) if filters.is_empty())
+    }
+}
+
+/// Pages with each tag, as bitmaps of rows in the page frame
+pub struct TagIndex {
+    /// Pages with each tag, indexed by tag id
+    tag_pages: Vec<RoaringBitmap>,
+    /// Every page, which NOT removes pages from
+    all_pages: RoaringBitmap,
+    /// Maps each tag name to its id
+    tag_ids: PlHashMap<String, u16>,
+}
+
+impl TagIndex {
+    /// Build the index from a page frame with a tags column, and the tags frame which names each
+    /// tag id
+    pub fn new(
+        page_frame: &DataFrame,
+        tags_frame: &DataFrame,
+    ) -> Result<TagIndex, RecommenderError> {
+        // Names can repeat, so there can be more ids than names
+        let mut tag_pages = vec![RoaringBitmap::new(); tags_frame.height()];
+        for (row, page_tags) in page_frame
+            .column("tags")?
+            .list()?
+            .into_no_null_iter()
+            .enumerate()
+        {
+            for tag in page_tags.u16()?.into_no_null_iter() {
+                if let Some(pages) = tag_pages.get_mut(tag as usize) {
+                    pages.insert(row as u32);
+                }
+            }
+        }
+
+        TagIndex::from_tag_pages(page_frame, tags_frame, tag_pages)
+    }
+
+    /// Load an index saved by `to_frame`, given the same page and tags frames it was built from
+    pub fn from_frame(
+        page_frame: &DataFrame,
+        tags_frame: &DataFrame,
+        frame: &DataFrame,
+    ) -> Result<TagIndex, RecommenderError> {
+        let corrupt = || {
+            RecommenderError::Snapshot(String::from("tag index does not match the pages and tags"))
+        };
+
+        let tag_pages = frame
+            .column("pages")?
+            .binary()?
+            .into_no_null_iter()
+            .map(|bytes| RoaringBitmap::deserialize_from(bytes).map_err(|_| corrupt()))
+            .collect::<Result<Vec<RoaringBitmap>, RecommenderError>>()?;
+
+        let page_count = page_frame.height() as u32;
+        if tag_pages.len() != tags_frame.height()
+            || tag_pages
+                .iter()
+                .any(|pages| pages.max().is_some_and(|row| row >= page_count))
+        {
+            return Err(corrupt());
+        }
+
+        TagIndex::from_tag_pages(page_frame, tags_frame, tag_pages)
+    }
+
+    /// The pages with each tag as a frame, with one serialized bitmap for each tag id, so that
+    /// snapshots do not need to rebuild the index
+    pub fn to_frame(&self) -> Result<DataFrame, RecommenderError> {
+        let mut pages = Vec::with_capacity(self.tag_pages.len());
+        for tag_pages in &self.tag_pages {
+            let mut bytes = Vec::with_capacity(tag_pages.serialized_size());
+            tag_pages.serialize_into(&mut bytes)?;
+            pages.push(bytes);
+        }
+
+        let pages: Vec<&[u8]> = pages.iter().map(Vec::as_slice).collect();
+        Ok(DataFrame::new(vec![Series::new("pages", pages)])?)
+    }
+
+    // Finish an index from the pages with each tag id
+    fn from_tag_pages(
+        page_frame: &DataFrame,
+        tags_frame: &DataFrame,
+        tag_pages: Vec<RoaringBitmap>,
+    ) -> Result<TagIndex, RecommenderError> {
+        let tag_ids: PlHashMap<String, u16> = tags_frame
+            .column("tag")?
+            .str()?
+            .into_no_null_iter()
+            .enumerate()
+            .map(|(id, tag)| (String::from(tag), id as u16))
+            .collect();
+
+        let mut all_pages = RoaringBitmap::new();
+        all_pages.insert_range(0..page_frame.height() as u32);
+
+        Ok(TagIndex {
+            tag_pages,
+            all_pages,
+            tag_ids,
+        })
+    }
+
+    /// Get the id of a tag from either its name or its id.
+    /// Names are checked first, since some tags (like "1000") are numbers.
+    pub fn get_id(&self, tag: &str) -> Option<u16> {
+        if let Some(id) = self.tag_ids.get(tag) {
+            return Some(*id);
+        }
+
+        tag.parse()
+            .ok()
+            .filter(|id| (*id as usize) < self.tag_pages.len())
+    }
+
+    /// Parse a tag filter expression.
+    /// Tags are names or ids, and can be combined with AND, OR, NOT, and parentheses.
+    /// Tags next to each other must all match, so a space separated list of tags requires every
+    /// one of them.
+    pub fn parse(&self, expression: &str) -> Result<TagFilter, RecommenderError> {
+        let tokens = tokenize(expression);
+        if tokens.len() > MAX_FILTER_TOKENS {
+            return Err(RecommenderError::TagFilter(format!(
+                "Filters can have at most {} tags and keywords",
+                MAX_FILTER_TOKENS
+            )));
+        }
+
+        let mut tokens = tokens.into_iter().peekable();
+        if tokens.peek().is_none() {
+            return Ok(TagFilter::default());
+        }
+
+        let filter = self.parse_any(&mut tokens, 0)?;
+
+        match tokens.next() {
+            None => Ok(filter),
+            Some(token) => Err(RecommenderError::TagFilter(format!("Unexpected {}", token))),
+        }
+    }
+
+    /// Get the rows of every page in the page frame which passes a filter
+    pub fn get_matching_pages(&self, filter: &TagFilter) -> RoaringBitmap {
+        match filter {
+            TagFilter::Tag(id) => self.tag_pages[*id as usize].clone(),
+            TagFilter::All(filters) => {
+                let mut pages = self.all_pages.clone();
+                for filter in filters {
+                    pages &= self.get_matching_pages(filter);
+                }
+                pages
+            }
+            TagFilter::Any(filters) => filters
+                .iter()
+                .map(|filter| self.get_matching_pages(filter))
+                .fold(RoaringBitmap::new(), |pages, matching| pages | matching),
+            TagFilter::Not(filter) => &self.all_pages - self.get_matching_pages(filter),
+        }
+    }
+
+    // any := all ("OR" all)*
+    // Depth is the number of parentheses and NOTs the tokens are inside
+    fn parse_any(
+        &self,
+        tokens: &mut Peekable<IntoIter<&str>>,
+        depth: usize,
+    ) -> Result<TagFilter, RecommenderError> {
+        let mut filters = vec![self.parse_all(tokens, depth)?];
+
+        while tokens.next_if_eq(&"OR").is_some() {
+            filters.push(self.parse_all(tokens, depth)?);
+        }
+
+        Ok(match filters.len() {
+            1 => filters.remove(0),
+            _ => TagFilter::Any(filters),
+        })
+    }
+
+    // all := not ("AND"? not)*
+    fn parse_all(
+        &self,
+        tokens: &mut Peekable<IntoIter<&str>>,
+        depth: usize,
+    ) -> Result<TagFilter, RecommenderError> {
+        let mut filters = vec![self.parse_not(tokens, depth)?];
+
+        loop {
+            if tokens.next_if_eq(&"AND").is_some() {
+                filters.push(self.parse_not(tokens, depth)?);
+                continue;
+            }
+
+            match tokens.peek() {
+                Some(&"OR") | Some(&")") | None => break,
+                Some(_) => filters.push(self.parse_not(tokens, depth)?),
+            }
+        }
+
+        Ok(match filters.len() {
+            1 => filters.remove(0),
+            _ => TagFilter::All(filters),
+        })
+    }
+
+    // not := "NOT" not | "(" any ")" | tag
+    fn parse_not(
+        &self,
+        tokens: &mut Peekable<IntoIter<&str>>,
+        depth: usize,
+    ) -> Result<TagFilter, RecommenderError> {
+        let token = tokens.next();
+        if matches!(token, Some("NOT" | "(")) && depth >= MAX_FILTER_DEPTH {
+            return Err(RecommenderError::TagFilter(format!(
+                "Filters can have at most {} parentheses and NOTs inside each other",
+                MAX_FILTER_DEPTH
+            )));
+        }
+
+        match token {
+            Some("NOT") => Ok(TagFilter::Not(Box::new(self.parse_not(tokens, depth + 1)?))),
+            Some("(") => {
+                let filter = self.parse_any(tokens, depth + 1)?;
+                match tokens.next() {
+                    Some(")") => Ok(filter),
+                    _ => Err(RecommenderError::TagFilter(String::from(
+                        "Missing closing parenthesis",
+                    ))),
+                }
+            }
+            Some(token @ (")" | "AND" | "OR")) => Err(RecommenderError::TagFilter(format!(
+                "Expected a tag before {}",
+                token
+            ))),
+            Some(tag) => match self.get_id(tag) {
+                Some(id) => Ok(TagFilter::Tag(id)),
+                None => Err(RecommenderError::TagFilter(format!("Unknown tag: {}", tag))),
+            },
+            None => Err(RecommenderError::TagFilter(String::from(
+                "Expected a tag at the end",
+            ))),
+        }
+    }
+}
+
+// Split an expression into tags, keywords, and parentheses
+fn tokenize(expression: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+
+    for (i, c) in expression.char_indices() {
+        if c.is_whitespace() || c == '(' || c == ')' {
+            if let Some(start) = start.take() {
+                tokens.push(&expression[start..i]);
+            }
+            if !c.is_whitespace() {
+                tokens.push(&expression[i..i + 1]);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+
+    if let Some(start) = start {
+        tokens.push(&expression[start..]);
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_index() -> TagIndex {
+        let tags =
+            df!("tag" => ["scp", "horror", "cosmic", "joke", "1000"]).expect("Tags not made");
+        let tag_lists = [
+            Series::new("", [0u16, 1]),
+            Series::new("", [0u16, 2, 3]),
+            Series::new("", [0u16, 2]),
+            Series::new("", [1u16, 4]),
+        ];
+        let frame = df!("tags" => tag_lists).expect("Frame not made");
+
+        TagIndex::new(&frame, &tags).expect("Index not made")
+    }
+
+    fn matching_rows(index: &TagIndex, expression: &str) -> Vec<u32> {
+        let filter = index.parse(expression).expect("Filter not parsed");
+        index.get_matching_pages(&filter).into_iter().collect()
+    }
+
+    #[test]
+    fn filter_by_expression() {
+        let index = test_index();
+
+        assert_eq!(matching_rows(&index, ""), vec![0, 1, 2, 3]);
+        // Lists of tags, by name or id, require all of them
+        assert_eq!(matching_rows(&index, "scp 2"), vec![1, 2]);
+        assert_eq!(
+            matching_rows(&index, "scp AND (horror OR cosmic) AND NOT joke"),
+            vec![0, 2]
+        );
+        assert_eq!(matching_rows(&index, "NOT scp"), vec![3]);
+        // Names come before ids
+        assert_eq!(matching_rows(&index, "1000"), vec![3]);
+
+        for bad in ["scp AND", "(scp", "scp)", "OR horror", "ghost", "5000"] {
+            assert!(index.parse(bad).is_err(), "{} was parsed", bad);
+        }
+    }
+
+    #[test]
+    fn index_repeated_names() {
+        // The last tag has an id past the number of distinct names
+        let tags = df!("tag" => ["scp", "horror", "scp", "joke"]).expect("Tags not made");
+        let tag_lists = [Series::new("", [0u16, 3]), Series::new("", [1u16, 2])];
+        let frame = df!("tags" => tag_lists).expect("Frame not made");
+        let index = TagIndex::new(&frame, &tags).expect("Index not made");
+
+        assert_eq!(matching_rows(&index, "joke"), vec![0]);
+        assert_eq!(matching_rows(&index, "3"), vec![0]);
+    }
+
+    #[test]
+    fn limit_nesting() {
+        let index = test_index();
+
+        let nested =
+            |depth: usize| format!("{}scp{}", "(NOT ".repeat(depth / 2), ")".repeat(depth / 2));
+        assert_eq!(matching_rows(&index, &nested(32)), vec![0, 1, 2]);
+
+        // Deep expressions would otherwise overflow the stack
+        for bad in [
+            nested(34),
+            format!("{}scp", "NOT ".repeat(200)),
+            format!("{}scp", "NOT ".repeat(3000)),
+            format!("{}scp{}", "(".repeat(3000), ")".repeat(3000)),
+            vec!["scp"; 300].join(" OR "),
+        ] {
+            assert!(
+                matches!(index.parse(&bad), Err(RecommenderError::TagFilter(_))),
+                "{} was parsed",
+                bad
+            );
+        }
+    }
+}
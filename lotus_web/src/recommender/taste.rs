@@ -0,0 +1,203 @@
+use super::{
+    NeighborContribution, Recommender, RecommenderError, TagAffinity, TasteProfile,
+    VOTE_UNCERTAINTY,
+};
+use polars_lazy::prelude::*;
+use tracing::instrument;
+
+/// Number of tags in each list of a taste profile
+const PROFILE_TAGS: usize = 10;
+
+/// Number of similar users in a taste profile
+const PROFILE_USERS: usize = 10;
+
+/// Fewest votes a tag needs, either from the user or expected from the wiki's average, before it
+/// is compared with the average. Rarer tags vary too much to say anything about
+const MIN_TAG_VOTES: f64 = 5f64;
+
+impl Recommender {
+    /// Describe what the recommender knows about a user's taste: how they vote, the tags they
+    /// like, the users most like them, and how they differ from the rest of the wiki.
+    /// The wiki's average is made from every vote on every page, so it includes users with too
+    /// few votes to be recommended to.
+    #[instrument(skip(self))]
+    pub fn get_taste_profile(&self, uid: u64) -> Result<TasteProfile, RecommenderError> {
+        let tags: Vec<&str> = self
+            .tags_frame
+            .column("tag")?
+            .str()?
+            .into_no_null_iter()
+            .collect();
+        let page_tags = self.page_frame.column("tags")?.list()?;
+        let page_votes: Vec<u32> = self
+            .page_frame
+            .column("votes")?
+            .u32()?
+            .into_no_null_iter()
+            .collect();
+
+        // Votes on pages with each tag, from every user on the wiki
+        let mut wiki_tag_votes = vec![0u64; tags.len()];
+        // Votes on each page times the number of votes on it, for the average popularity of the
+        // page a vote is on
+        let mut wiki_popularity = 0f64;
+        for (page, votes) in page_tags.into_iter().zip(page_votes.iter()) {
+            if let Some(page) = page {
+                for tag in page.u16()?.into_no_null_iter() {
+                    if let Some(tag_votes) = wiki_tag_votes.get_mut(tag as usize) {
+                        *tag_votes += *votes as u64;
+                    }
+                }
+            }
+            wiki_popularity += (*votes as f64).powi(2);
+        }
+        let wiki_votes: u64 = page_votes.iter().map(|votes| *votes as u64).sum();
+
+        let mut upvotes = 0u32;
+        let mut downvotes = 0u32;
+        let mut popularity = 0f64;
+        let mut user_tag_votes = vec![(0u32, 0u32); tags.len()];
+        for (pid, upvote) in self.get_votes(uid)? {
+            match upvote {
+                true => upvotes += 1,
+                false => downvotes += 1,
+            }
+
+            // Votes on pages without any info are still counted, just not by tag
+            let Some(row) = self.page_map.get(&pid) else {
+                continue;
+            };
+            popularity += page_votes[*row] as f64;
+
+            let Some(page) = page_tags.get_as_series(*row) else {
+                continue;
+            };
+            for tag in page.u16()?.into_no_null_iter() {
+                if let Some((tag_upvotes, tag_downvotes)) = user_tag_votes.get_mut(tag as usize) {
+                    match upvote {
+                        true => *tag_upvotes += 1,
+                        false => *tag_downvotes += 1,
+                    }
+                }
+            }
+        }
+
+        let votes = upvotes + downvotes;
+        let user_votes = votes.max(1) as f64;
+        let wiki_votes_f = wiki_votes.max(1) as f64;
+
+        let affinities: Vec<TagAffinity> = tags
+            .iter()
+            .zip(user_tag_votes.iter().zip(wiki_tag_votes.iter()))
+            // Tags starting with an underscore are for the wiki's own use, like "_licensebox"
+            .filter(|(tag, _)| !tag.starts_with('_'))
+            .map(
+                |(tag, ((upvotes, downvotes), tag_wiki_votes))| TagAffinity {
+                    tag: String::from(*tag),
+                    upvotes: *upvotes,
+                    downvotes: *downvotes,
+                    share: (upvotes + downvotes) as f64 / user_votes,
+                    wiki_share: *tag_wiki_votes as f64 / wiki_votes_f,
+                    affinity: (*upvotes as f64 - *downvotes as f64) / user_votes,
+                },
+            )
+            .collect();
+
+        let mut top_tags: Vec<TagAffinity> = affinities
+            .iter()
+            .filter(|tag| tag.affinity > 0f64)
+            .cloned()
+            .collect();
+        top_tags.sort_by(|a, b| b.affinity.total_cmp(&a.affinity));
+        top_tags.truncate(PROFILE_TAGS);
+
+        // Only tags which are common enough for the difference to mean something are compared
+        let mut compared: Vec<(f64, &TagAffinity)> = affinities
+            .iter()
+            .filter(|tag| {
+                let tag_votes = (tag.upvotes + tag.downvotes) as f64;
+                tag_votes.max(tag.wiki_share * votes as f64) >= MIN_TAG_VOTES
+            })
+            .map(|tag| (tag.share / tag.wiki_share.max(f64::MIN_POSITIVE), tag))
+            .collect();
+        compared.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+        let more_than_average = compared
+            .iter()
+            .take_while(|(ratio, _)| *ratio > 1f64)
+            .take(PROFILE_TAGS)
+            .map(|(_, tag)| (*tag).clone())
+            .collect();
+        let less_than_average = compared
+            .iter()
+            .rev()
+            .take_while(|(ratio, _)| *ratio < 1f64)
+            .take(PROFILE_TAGS)
+            .map(|(_, tag)| (*tag).clone())
+            .collect();
+
+        let neighbors = self.get_neighbors(uid)?.head(Some(PROFILE_USERS));
+        let neighbor_uids: Vec<&str> = neighbors
+            .column("uid")?
+            .str()?
+            .into_no_null_iter()
+            .collect();
+        let similar_users = self
+            .get_usernames(&neighbor_uids)?
+            .into_iter()
+            .zip(neighbors.column("similarity")?.f64()?.into_no_null_iter())
+            .map(|(name, similarity)| NeighborContribution { name, similarity })
+            .collect();
+
+        let name = self
+            .get_usernames(&[uid.to_string().as_str()])?
+            .pop()
+            .ok_or(RecommenderError::UserNotFound)?;
+
+        Ok(TasteProfile {
+            name,
+            uid,
+            votes,
+            upvotes,
+            downvotes,
+            upvote_ratio: match votes {
+                0 => 0f64,
+                _ => upvotes as f64 / votes as f64,
+            },
+            top_tags,
+            similar_users,
+            more_than_average,
+            less_than_average,
+            average_page_votes: popularity / user_votes,
+            wiki_average_page_votes: wiki_popularity / wiki_votes_f,
+        })
+    }
+
+    // Get every page the given user voted on, and whether they upvoted it
+    pub(super) fn get_votes(&self, uid: u64) -> Result<Vec<(u64, bool)>, RecommenderError> {
+        let uid_str = format!("{}", uid);
+        let unvote = match self.middle_norms.get(&uid_str) {
+            Some(value) => *value,
+            None => return Err(RecommenderError::UserNotFound),
+        };
+
+        let votes = self
+            .rating_frame
+            .clone()
+            .select([col("pid"), col(&uid_str)])
+            .filter(
+                col(&uid_str)
+                    .gt(lit(unvote + VOTE_UNCERTAINTY))
+                    .or(col(&uid_str).lt(lit(unvote - VOTE_UNCERTAINTY))),
+            )
+            .collect()?;
+
+        Ok(votes
+            .column("pid")?
+            .u64()?
+            .into_no_null_iter()
+            .zip(votes.column(&uid_str)?.f64()?.into_no_null_iter())
+            .map(|(pid, value)| (pid, value > unvote))
+            .collect())
+    }
+}
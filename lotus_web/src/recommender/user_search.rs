@@ -0,0 +1,291 @@
+use super::RecommenderError;
+use fst::{
+    automaton::{Levenshtein, Str},
+    Automaton, IntoStreamer, Map, MapBuilder, Streamer,
+};
+use polars::prelude::*;
+use serde::Serialize;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// Most keys looked at for each kind of match, so that very short queries stay fast
+const MAX_SCANNED_KEYS: usize = 1000;
+
+/// A user matching a search
+#[derive(Clone, Debug, Serialize)]
+pub struct UserMatch {
+    pub name: String,
+    pub uid: u64,
+    /// The user's page on the wiki is `https://www.wikidot.com/user:info/<slug>`
+    pub slug: String,
+}
+
+/// Index of every user by both their name and their url slug.
+/// Keys are folded so that case, accents, spaces, and punctuation never stop a match.
+pub struct UserIndex {
+    /// Maps each folded key to its position in `groups`
+    keys: Map<Vec<u8>>,
+    /// Rows of the user frame sharing each key, since folding can make different names equal
+    groups: Vec<Vec<usize>>,
+    users: Vec<UserMatch>,
+}
+
+// How well a key matched a query. Lower is better
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum MatchKind {
+    Exact,
+    Prefix,
+    Typo,
+    TypoPrefix,
+}
+
+impl UserIndex {
+    /// Build the index from a user frame with name, url, and uid columns
+    pub fn new(user_frame: &DataFrame) -> Result<UserIndex, RecommenderError> {
+        let users = get_users(user_frame)?;
+
+        let mut entries: Vec<(String, usize)> = users
+            .iter()
+            .enumerate()
+            .flat_map(|(row, user)| [(fold(&user.name), row), (fold(&user.slug), row)])
+            .filter(|(key, _)| !key.is_empty())
+            .collect();
+        entries.sort_unstable();
+        entries.dedup();
+
+        // FST keys must be unique and inserted in order, so equal keys share a group
+        let mut builder = MapBuilder::memory();
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut last_key: Option<&str> = None;
+
+        for (key, row) in &entries {
+            if last_key != Some(key.as_str()) {
+                builder
+                    .insert(key, groups.len() as u64)
+                    .expect("Keys are sorted and unique");
+                groups.push(Vec::new());
+                last_key = Some(key);
+            }
+
+            let group = groups.last_mut().expect("A group was just added");
+            if !group.contains(row) {
+                group.push(*row);
+            }
+        }
+
+        let keys = builder.into_map();
+
+        Ok(UserIndex {
+            keys,
+            groups,
+            users,
+        })
+    }
+
+    /// Load an index saved by `to_frames`, given the same user frame it was built from
+    pub fn from_frames(
+        user_frame: &DataFrame,
+        keys: &DataFrame,
+        groups: &DataFrame,
+    ) -> Result<UserIndex, RecommenderError> {
+        let corrupt =
+            || RecommenderError::Snapshot(String::from("user index does not match the users"));
+
+        let keys = keys.column("keys")?.binary()?.get(0).ok_or_else(corrupt)?;
+        let keys = Map::new(keys.to_vec()).map_err(|_| corrupt())?;
+
+        let groups = groups
+            .column("rows")?
+            .list()?
+            .into_no_null_iter()
+            .map(|rows| {
+                Ok(rows
+                    .u32()?
+                    .into_no_null_iter()
+                    .map(|row| row as usize)
+                    .collect())
+            })
+            .collect::<Result<Vec<Vec<usize>>, PolarsError>>()?;
+
+        // Keys are numbered in order, so every number is a group if the counts match
+        let users = get_users(user_frame)?;
+        if keys.len() != groups.len() || groups.iter().flatten().any(|row| *row >= users.len()) {
+            return Err(corrupt());
+        }
+
+        Ok(UserIndex {
+            keys,
+            groups,
+            users,
+        })
+    }
+
+    /// The index as frames, so that snapshots do not need to rebuild it.
+    /// These are the key map as a single value of bytes, and the rows sharing each key.
+    pub fn to_frames(&self) -> Result<(DataFrame, DataFrame), RecommenderError> {
+        let keys = DataFrame::new(vec![Series::new("keys", [self.keys.as_fst().as_bytes()])])?;
+
+        let rows: Vec<Series> = self
+            .groups
+            .iter()
+            .map(|group| {
+                let rows: Vec<u32> = group.iter().map(|row| *row as u32).collect();
+                Series::new("", rows)
+            })
+            .collect();
+        let groups = DataFrame::new(vec![Series::new("rows", rows)])?;
+
+        Ok((keys, groups))
+    }
+
+    /// Find the users best matching a query, best first.
+    /// Exact matches come first, then names starting with the query, then names which match
+    /// apart from a typo or two.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<UserMatch> {
+        let query = fold(query);
+        if query.is_empty() || limit == 0 {
+            return Vec::new();
+        }
+
+        // (kind, key length, row) for every match, where shorter keys are closer to the query
+        let mut found: Vec<(MatchKind, usize, usize)> = Vec::new();
+
+        if let Some(group) = self.keys.get(&query) {
+            self.add_group(&mut found, group, MatchKind::Exact, query.len());
+        }
+
+        self.add_matches(
+            &mut found,
+            Str::new(&query).starts_with(),
+            MatchKind::Prefix,
+        );
+
+        // Allow more typos in longer queries, where they are both more likely and less ambiguous
+        let distance = match query.chars().count() {
+            0..=3 => 0,
+            4..=7 => 1,
+            _ => 2,
+        };
+
+        if distance > 0 {
+            // Building the automaton fails if the query is huge, in which case typos are ignored
+            if let Ok(typo) = Levenshtein::new(&query, distance) {
+                self.add_matches(&mut found, &typo, MatchKind::Typo);
+                self.add_matches(&mut found, typo.starts_with(), MatchKind::TypoPrefix);
+            }
+        }
+
+        found.sort_unstable();
+
+        let mut rows = Vec::with_capacity(limit);
+        for (_, _, row) in found {
+            if !rows.contains(&row) {
+                rows.push(row);
+                if rows.len() == limit {
+                    break;
+                }
+            }
+        }
+
+        rows.into_iter()
+            .map(|row| self.users[row].clone())
+            .collect()
+    }
+
+    fn add_matches<A: Automaton>(
+        &self,
+        found: &mut Vec<(MatchKind, usize, usize)>,
+        automaton: A,
+        kind: MatchKind,
+    ) {
+        let mut stream = self.keys.search(automaton).into_stream();
+        let mut scanned = 0;
+
+        while let Some((key, group)) = stream.next() {
+            self.add_group(found, group, kind, key.len());
+
+            scanned += 1;
+            if scanned == MAX_SCANNED_KEYS {
+                break;
+            }
+        }
+    }
+
+    fn add_group(
+        &self,
+        found: &mut Vec<(MatchKind, usize, usize)>,
+        group: u64,
+        kind: MatchKind,
+        key_length: usize,
+    ) {
+        for row in &self.groups[group as usize] {
+            found.push((kind, key_length, *row));
+        }
+    }
+}
+
+// Get the name, uid, and slug of every user in a user frame, in the same order
+fn get_users(user_frame: &DataFrame) -> Result<Vec<UserMatch>, RecommenderError> {
+    let names = user_frame.column("name")?.str()?;
+    let slugs = user_frame.column("url")?.str()?;
+    let uids = user_frame.column("uid")?.u64()?;
+
+    Ok(names
+        .into_no_null_iter()
+        .zip(slugs.into_no_null_iter())
+        .zip(uids.into_no_null_iter())
+        .map(|((name, slug), uid)| UserMatch {
+            name: String::from(name),
+            uid,
+            slug: String::from(slug),
+        })
+        .collect())
+}
+
+/// Fold a name or query so that only the letters and numbers in it matter.
+/// Accents are removed, so "Café" and "cafe" match.
+pub fn fold(text: &str) -> String {
+    text.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .filter(|c| c.is_alphanumeric())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_index() -> UserIndex {
+        let frame = df!(
+            "name" => ["studyGT", "Café Noir", "Dr Bright", "Dr Brightly"],
+            "url" => ["study-gt", "cafe-noir", "dr-bright", "brightly"],
+            "uid" => [1u64, 2, 3, 4],
+        )
+        .expect("Frame not made");
+
+        UserIndex::new(&frame).expect("Index not made")
+    }
+
+    fn search_uids(index: &UserIndex, query: &str) -> Vec<u64> {
+        index
+            .search(query, 10)
+            .iter()
+            .map(|user| user.uid)
+            .collect()
+    }
+
+    #[test]
+    fn search_users() {
+        let index = test_index();
+
+        assert_eq!(search_uids(&index, "Study GT"), vec![1]);
+        assert_eq!(search_uids(&index, "cafe"), vec![2]);
+        // Exact matches come before longer names starting with the query
+        assert_eq!(search_uids(&index, "dr bright"), vec![3, 4]);
+        // Typos are forgiven
+        assert_eq!(search_uids(&index, "studyGY"), vec![1]);
+        // Slugs can be searched as well as names
+        assert_eq!(search_uids(&index, "brightly"), vec![4]);
+        assert!(search_uids(&index, "nobody").is_empty());
+    }
+}
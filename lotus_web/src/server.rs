@@ -1,21 +1,123 @@
-use crate::{
-    recommender::{Recommender, RecommenderError},
-    SERVER_HEADING,
+use crate::recommender::{
+    Explanation, GroupStrategy, LocalRating, Ranking, Recommender, RecommenderError, TagFilter,
+    TasteProfile,
 };
 use askama_axum::Template;
-use axum::{self, extract::State};
+use axum::{
+    self,
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        Path, Query, State,
+    },
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
 use polars::prelude::*;
-use serde::Serialize;
-use serde_json;
-use std::collections::HashMap;
-use urlencoding;
-
-#[derive(Serialize)]
-struct Recommendation {
-    name: String,
-    url: String,
-    tags: Vec<String>,
-    pid: u64,
+use std::{
+    str::FromStr,
+    sync::{Arc, OnceLock},
+    time::Instant,
+};
+use tokio::task;
+use tracing::{debug, error, warn};
+
+mod cache;
+mod lifecycle;
+mod lists;
+mod metrics;
+mod profiles;
+mod server_types;
+mod work_pool;
+
+pub use cache::{CacheStats, RankingCache, RankingKey};
+pub use lifecycle::{get_listener, shutdown_signal, take_activated_listener};
+pub use lists::{CachedJson, Lists};
+pub use metrics::{track_requests, Metrics};
+pub use profiles::{Mark, MarkKind, Profile, ProfileError, ProfileStore};
+pub use server_types::{
+    ApiError, BannedPage, CompareQuery, CompareResponse, CreatedProfile, Cursor, GroupQuery,
+    InfoResponse, MarkedPage, PageSearchResponse, ProfileMarkBody, ProfileResponse,
+    ProfileTagsBody, Recommendation, RecommendationForm, RecommendationQuery,
+    RecommendationsResponse, SearchQuery, ServerInfo, ServingOptions, UserSearchResponse,
+};
+pub use work_pool::WorkPool;
+
+/// Number of recommendations returned when no limit is given
+const DEFAULT_LIMIT: usize = 500;
+
+/// Most recommendations which can be returned by a single request
+const MAX_LIMIT: usize = 500;
+
+/// Number of recommendations shown at once on the recommendations page when no limit is given
+const DEFAULT_PAGE_LIMIT: usize = 30;
+
+/// Most users which can be in a group given recommendations together
+const MAX_GROUP_SIZE: usize = 10;
+
+/// Number of results returned by a search when no limit is given
+const DEFAULT_SEARCH_LIMIT: usize = 10;
+
+/// Most results which can be returned by a single search
+const MAX_SEARCH_LIMIT: usize = 50;
+
+/// State shared by every request handler
+#[derive(Clone)]
+pub struct AppState {
+    /// Set once the recommender has loaded.
+    /// The server listens while it loads, so that health checks can tell it is still starting.
+    pub recommender: Arc<OnceLock<Recommender>>,
+    pub cache: Arc<RankingCache>,
+    /// Pool which all recommendation work is run through
+    pub pool: Arc<WorkPool>,
+    pub metrics: Arc<Metrics>,
+    pub info: Arc<ServerInfo>,
+    /// Made from the recommender the first time they are requested
+    pub lists: Arc<OnceLock<Lists>>,
+    /// Saved user profiles, if they are turned on
+    pub profiles: Option<Arc<ProfileStore>>,
+}
+
+impl AppState {
+    /// Returns the recommender, or a 503 error if it has not loaded yet
+    pub fn get_recommender(&self) -> Result<&Recommender, ApiError> {
+        self.recommender.get().ok_or_else(ApiError::not_ready)
+    }
+
+    /// Returns the tag and user lists, or a 503 error if the recommender has not loaded yet
+    pub fn get_lists(&self) -> Result<&Lists, ApiError> {
+        let recommender = self.get_recommender()?;
+
+        Ok(self.lists.get_or_init(|| Lists::new(recommender)))
+    }
+
+    /// Run a job on the profile store away from the async threads, since SQLite blocks.
+    /// Returns a 404 error if profiles are turned off.
+    pub async fn with_profiles<T, F>(&self, job: F) -> Result<T, ApiError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&ProfileStore) -> Result<T, ProfileError> + Send + 'static,
+    {
+        let profiles = match &self.profiles {
+            Some(profiles) => Arc::clone(profiles),
+            None => {
+                return Err(ApiError::not_found(
+                    "PROFILES_DISABLED",
+                    "This server does not keep profiles",
+                ))
+            }
+        };
+
+        task::spawn_blocking(move || job(&profiles))
+            .await
+            .expect("Profile jobs should not panic")
+            .inspect_err(|e| {
+                if let ProfileError::Database(_) = e {
+                    error!(error = ?e, "Profile store failed");
+                }
+            })
+            .map_err(ApiError::from)
+    }
 }
 
 #[derive(Template)]
@@ -25,8 +127,8 @@ pub struct RootTemplate {
 }
 
 /// Display the homepage
-pub async fn root(State(recommender): State<Arc<Recommender>>) -> RootTemplate {
-    let rec_tags = recommender.get_tags();
+pub async fn root(State(state): State<AppState>) -> Result<RootTemplate, ApiError> {
+    let rec_tags = state.get_recommender()?.get_tags();
 
     // Capacity is definately at least the 29 characters per HTML tag plus a minimum of 1 character
     // per tag name.
@@ -40,129 +142,981 @@ pub async fn root(State(recommender): State<Arc<Recommender>>) -> RootTemplate {
         tag_elements.push_str(tag_element.as_str());
     }
 
-    RootTemplate { tag_elements }
+    Ok(RootTemplate { tag_elements })
+}
+
+#[derive(Template)]
+#[template(path = "recommendations.html")]
+pub struct RecommendationsTemplate {
+    form: RecommendationForm,
+    bans: Vec<BannedPage>,
+    results: Option<RecommendationsResponse>,
+    /// Offset of the previous window of recommendations, if this is not the first
+    previous_offset: Option<usize>,
+    error: Option<String>,
+}
+
+/// Display recommendations as plain HTML, with forms for filtering, paging, and banning pages.
+/// This works without JavaScript, and every page of results has a url which can be shared.
+pub async fn recommendations_page(
+    State(state): State<AppState>,
+    query: Result<Query<RecommendationQuery>, QueryRejection>,
+) -> (StatusCode, RecommendationsTemplate) {
+    let mut template = RecommendationsTemplate {
+        form: RecommendationForm::default(),
+        bans: Vec::new(),
+        results: None,
+        previous_offset: None,
+        error: None,
+    };
+
+    let params = match query {
+        Ok(Query(params)) => params,
+        Err(e) => {
+            let e = ApiError::from(e);
+            template.error = Some(String::from(e.get_message()));
+            return (e.get_status(), template);
+        }
+    };
+
+    template.form = RecommendationForm::from(&params);
+
+    // Nothing is shown until a user is given, so that the page can be linked to as a blank form
+    if template.form.user.is_empty() {
+        return (StatusCode::OK, template);
+    }
+
+    match get_page_results(&state, params).await {
+        Ok((results, previous_offset, bans)) => {
+            template.results = Some(results);
+            template.previous_offset = previous_offset;
+            template.bans = bans;
+            (StatusCode::OK, template)
+        }
+        Err(e) => {
+            template.error = Some(String::from(e.get_message()));
+            (e.get_status(), template)
+        }
+    }
+}
+
+// Make the recommendations shown on the recommendations page, along with the offset of the
+// previous window and the banned pages
+async fn get_page_results(
+    state: &AppState,
+    mut params: RecommendationQuery,
+) -> Result<(RecommendationsResponse, Option<usize>, Vec<BannedPage>), ApiError> {
+    params.limit = params.limit.or(Some(DEFAULT_PAGE_LIMIT));
+
+    // Forms cannot send headers, so profiles are never used here
+    let uid = get_user(state.get_recommender()?, &params)?;
+    let request = check_request(state, vec![uid], None, params).await?;
+    let (offset, limit) = (request.window.offset, request.window.limit);
+
+    let recommender = state.get_recommender()?;
+    let bans = request
+        .bans
+        .iter()
+        .map(|pid| BannedPage {
+            pid: *pid,
+            name: get_page_name(recommender, *pid).unwrap_or_else(|| pid.to_string()),
+            other_bans: request
+                .bans
+                .iter()
+                .filter(|other| *other != pid)
+                .map(u64::to_string)
+                .collect::<Vec<String>>()
+                .join(" "),
+        })
+        .collect();
+
+    let state = state.clone();
+    let pool = Arc::clone(&state.pool);
+    let results = pool.run(move || recommend(&state, request)).await?;
+
+    let previous_offset = (offset > 0).then(|| offset.saturating_sub(limit));
+
+    Ok((results, previous_offset, bans))
 }
 
-/// Returns a list of recommendations in JSON format with the given params
+/// Returns a list of recommendations in JSON format with the given params.
+/// If a profile token is given, its marked pages are banned, its upvotes and downvotes are added
+/// to the user's votes, and its tag filter is used when the request has none.
 pub async fn get_rec(
-    State(recommender): State<Arc<Recommender>>,
-    axum::extract::Query(params): axum::extract::Query<HashMap<String, String>>,
-) -> String {
-    eprintln!(
-        "{}Recommendation request with params: {:?}",
-        SERVER_HEADING, params
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    query: Result<Query<RecommendationQuery>, QueryRejection>,
+) -> Result<Json<RecommendationsResponse>, ApiError> {
+    let Query(params) = query?;
+    let uid = get_user(state.get_recommender()?, &params)?;
+    let request = check_request(&state, vec![uid], get_token(&headers), params).await?;
+
+    let pool = Arc::clone(&state.pool);
+    let response = pool.run(move || recommend(&state, request)).await?;
+
+    Ok(Json(response))
+}
+
+/// Returns a list of recommendations in JSON format for a group of users, ordered by how much the
+/// group as a whole should like them. Pages any member has voted on are never recommended.
+/// If a profile token is given, its marked pages are banned and its tag filter is used when the
+/// request has none.
+pub async fn get_group_rec(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    query: Result<Query<GroupQuery>, QueryRejection>,
+) -> Result<Json<RecommendationsResponse>, ApiError> {
+    let Query(params) = query?;
+    let recommender = state.get_recommender()?;
+
+    let mut uids = Vec::new();
+    for user_string in params.users.as_deref().unwrap_or_default().split(',') {
+        let user_string = user_string.trim();
+        if user_string.is_empty() {
+            continue;
+        }
+
+        let uid = get_uid(recommender, user_string)?;
+        if !uids.contains(&uid) {
+            uids.push(uid);
+        }
+    }
+
+    if uids.len() < 2 {
+        return Err(ApiError::bad_request(
+            "NO_GROUP",
+            "At least two different users must be given",
+        ));
+    }
+    if uids.len() > MAX_GROUP_SIZE {
+        return Err(ApiError::bad_request(
+            "GROUP_TOO_LARGE",
+            format!("A group can have at most {} users", MAX_GROUP_SIZE),
+        ));
+    }
+
+    let mut request = check_request(&state, uids, get_token(&headers), params.options).await?;
+    request.strategy = params.strategy.unwrap_or_default();
+
+    let pool = Arc::clone(&state.pool);
+    let response = pool.run(move || recommend(&state, request)).await?;
+
+    Ok(Json(response))
+}
+
+// Get the uid of the user a recommendation request is for
+fn get_user(recommender: &Recommender, params: &RecommendationQuery) -> Result<u64, ApiError> {
+    match params.user.as_deref() {
+        Some(user_string) if !user_string.is_empty() => get_uid(recommender, user_string),
+        _ => Err(ApiError::bad_request("NO_USER", "No user was given")),
+    }
+}
+
+// Parse and check a recommendation request for one user or a group, adding the bans, ratings,
+// and tags of the profile with the given token if there is one.
+// A profile's ratings are only used for a single user, since they are that user's votes
+async fn check_request(
+    state: &AppState,
+    uids: Vec<u64>,
+    token: Option<String>,
+    mut params: RecommendationQuery,
+) -> Result<CheckedRequest, ApiError> {
+    let recommender = state.get_recommender()?;
+
+    let mut bans: Vec<u64> = parse_ids(params.bans.as_deref())
+        .map_err(|ban| ApiError::bad_request("INVALID_BANS", format!("Invalid ban: {}", ban)))?;
+
+    let mut ratings = Vec::new();
+    if let Some(token) = token {
+        let profile = state.with_profiles(move |store| store.get(&token)).await?;
+
+        // Every rated page is also marked, so a group still never sees them
+        bans.extend(profile.get_marked_pids());
+        if uids.len() == 1 {
+            ratings.extend(profile.get_ratings());
+        }
+        if params.tags.is_none() {
+            params.tags = profile.tags;
+        }
+    }
+
+    // Ratings in the url come after the profile's, so that they replace them
+    for (pids, upvote) in [(&params.likes, true), (&params.dislikes, false)] {
+        let pids: Vec<u64> = parse_ids(pids.as_deref()).map_err(|pid| {
+            ApiError::bad_request("INVALID_RATINGS", format!("Invalid rating: {}", pid))
+        })?;
+        ratings.extend(pids.into_iter().map(|pid| LocalRating { pid, upvote }));
+    }
+
+    let tags = get_tag_filter(recommender, &params)?;
+
+    let diversity = params.diversity.unwrap_or(0f64);
+    if !(0f64..=1f64).contains(&diversity) {
+        return Err(ApiError::bad_request(
+            "INVALID_DIVERSITY",
+            "Diversity must be from 0 to 1",
+        ));
+    }
+
+    let novelty = params.novelty.unwrap_or_else(|| recommender.get_novelty());
+    if !(0f64..=1f64).contains(&novelty) {
+        return Err(ApiError::bad_request(
+            "INVALID_NOVELTY",
+            "Novelty must be from 0 to 1",
+        ));
+    }
+
+    let window = get_window(&params)?;
+    let explain = params.explain.unwrap_or(false);
+
+    // Ratings and explanations belong to one user, so they cannot be used for a group
+    if uids.len() > 1 && (explain || !ratings.is_empty()) {
+        return Err(ApiError::bad_request(
+            "NOT_FOR_GROUPS",
+            "Ratings and explanations cannot be used for a group",
+        ));
+    }
+
+    debug!(
+        ?uids,
+        ?tags,
+        bans = bans.len(),
+        ratings = ratings.len(),
+        diversity,
+        novelty,
+        offset = window.offset,
+        limit = window.limit,
+        explain,
+        "Recommendation request"
     );
 
-    let ban_param = params.get("bans");
-    let tag_param = params.get("tags");
-    let user_param = params.get("user");
+    Ok(CheckedRequest {
+        uids,
+        strategy: GroupStrategy::default(),
+        tags,
+        bans,
+        ratings,
+        diversity,
+        novelty,
+        window,
+        explain,
+    })
+}
 
-    // Check if the uid exists
-    let uid: u64 = if let Some(user_string) = user_param {
-        // Check if this a name
-        match recommender.get_user_by_username(user_string) {
-            Ok(user) => match user[2] {
-                AnyValue::UInt64(uid) => uid,
-                _ => unreachable!(),
-            },
-            Err(_) => {
-                // Check if this is is a raw uid as opposed to a username
-                match user_string.parse() {
-                    Ok(value) => value,
-                    Err(_) => return String::from(r#"{"type":"error","code":"USER_PARSE_ERROR"}"#),
-                }
+// A recommendation request which has been parsed and checked
+struct CheckedRequest {
+    /// Every user the recommendations are for, which is only one unless this is for a group
+    uids: Vec<u64>,
+    /// How the rankings of a group are combined
+    strategy: GroupStrategy,
+    tags: TagFilter,
+    bans: Vec<u64>,
+    ratings: Vec<LocalRating>,
+    diversity: f64,
+    novelty: f64,
+    window: Cursor,
+    explain: bool,
+}
+
+// Make recommendations for a request which has already been checked.
+// This is CPU-heavy, so it should only be run through the work pool.
+fn recommend(
+    state: &AppState,
+    request: CheckedRequest,
+) -> Result<RecommendationsResponse, ApiError> {
+    let recommender = state.get_recommender()?;
+    let CheckedRequest {
+        uids,
+        strategy,
+        tags,
+        bans,
+        ratings,
+        diversity,
+        novelty,
+        window,
+        explain,
+    } = request;
+
+    let mut rankings = Vec::with_capacity(uids.len());
+    for uid in uids {
+        let (ranking, cached) = state
+            .cache
+            .get_ranking(recommender, uid, &ratings, &tags)
+            .inspect_err(log_recommender_error)?;
+
+        // Cached rankings were already timed when they were made
+        if !cached {
+            state
+                .metrics
+                .observe_phase("similarity", ranking.get_similarity_time());
+            state
+                .metrics
+                .observe_phase("weighting", ranking.get_weighting_time());
+        }
+
+        rankings.push(ranking);
+    }
+
+    let start = Instant::now();
+    let recs = || -> Result<_, RecommenderError> {
+        let recs = match rankings.as_slice() {
+            [ranking] => recommender.filter_ranking(ranking, &tags, bans)?,
+            _ => {
+                let rankings: Vec<&Ranking> = rankings.iter().map(Arc::as_ref).collect();
+                let group = recommender.combine_rankings(&rankings, strategy)?;
+                recommender.filter_recommendations(&group, &tags, bans)?
             }
         }
+        .collect()?;
+        let recs = recommender.discount_popularity(recs, novelty)?;
+
+        recommender.diversify(recs, diversity)
+    }()
+    .inspect_err(log_recommender_error)?;
+    state.metrics.observe_phase("filtering", start.elapsed());
+
+    let total = recs.height();
+    let top_recs = recs.slice(window.offset.min(total) as i64, window.limit);
+
+    let next_offset = window.offset + top_recs.height();
+    let next_cursor = (next_offset < total).then(|| {
+        Cursor {
+            offset: next_offset,
+            limit: window.limit,
+        }
+        .encode()
+    });
+
+    // Groups are never explained, which was checked with the request
+    let explanations = if let (true, [ranking]) = (explain, rankings.as_slice()) {
+        let start = Instant::now();
+        let explanations = recommender
+            .explain(ranking, &top_recs)
+            .inspect_err(log_recommender_error)?;
+        state.metrics.observe_phase("explanation", start.elapsed());
+
+        Some(explanations)
     } else {
-        return String::from(r#"{"type":"error","code":"NO_USER"}"#);
+        None
     };
 
-    let tags: Vec<u16> = if let Some(tag_string) = tag_param {
-        tag_string
-            .split(" ")
-            .map(|tag| tag.parse().unwrap())
-            .collect()
-    } else {
-        Vec::new()
+    Ok(RecommendationsResponse {
+        recommendations: recs_to_vec(recommender, top_recs, explanations),
+        total,
+        offset: window.offset,
+        next_cursor,
+    })
+}
+
+// Log an error from the recommender before it is passed on to the client.
+// Missing users and pages are the client's mistake, so they are only a warning.
+fn log_recommender_error(e: &RecommenderError) {
+    match e {
+        RecommenderError::UserNotFound | RecommenderError::PageNotFound => {
+            warn!(error = ?e, "Recommender could not find requested data")
+        }
+        _ => error!(error = ?e, "Recommender failed"),
+    }
+}
+
+/// Reports that the process is alive, whether or not it can serve recommendations yet
+pub async fn get_health() -> &'static str {
+    "ok"
+}
+
+/// Reports whether the recommender has loaded, so that traffic can wait until it is served
+pub async fn get_ready(State(state): State<AppState>) -> (StatusCode, &'static str) {
+    match state.recommender.get() {
+        Some(_) => (StatusCode::OK, "ready"),
+        None => (StatusCode::SERVICE_UNAVAILABLE, "loading"),
+    }
+}
+
+/// Returns the server's version, settings, and how much data it is serving
+pub async fn get_info(State(state): State<AppState>) -> Json<InfoResponse> {
+    let recommender = state.recommender.get();
+    let model = recommender.and_then(|recommender| {
+        recommender
+            .get_model_size()
+            .inspect_err(log_recommender_error)
+            .ok()
+    });
+
+    Json(InfoResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        ready: recommender.is_some(),
+        scraped_at: recommender.and_then(Recommender::get_scraped_at),
+        model,
+        options: state.info.options.clone(),
+    })
+}
+
+/// Returns the name of every tag, with each tag's id being its index
+pub async fn get_tags(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    Ok(state.get_lists()?.tags.respond(&headers))
+}
+
+/// Returns the name of every user who can be recommended to
+pub async fn get_users(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    Ok(state.get_lists()?.users.respond(&headers))
+}
+
+/// Returns what the recommender knows about a user's taste, found by username or uid
+pub async fn get_user_taste(
+    State(state): State<AppState>,
+    user: Result<Path<String>, PathRejection>,
+) -> Result<Json<TasteProfile>, ApiError> {
+    let Path(user) = user?;
+
+    Ok(Json(get_taste_profile(state, &user).await?))
+}
+
+#[derive(Template)]
+#[template(path = "user.html")]
+pub struct UserTemplate {
+    /// The username or uid which was asked for
+    user: String,
+    profile: Option<TasteProfile>,
+    error: Option<String>,
+}
+
+/// Display what the recommender knows about a user's taste
+pub async fn user_page(
+    State(state): State<AppState>,
+    user: Result<Path<String>, PathRejection>,
+) -> (StatusCode, UserTemplate) {
+    let user = match user {
+        Ok(Path(user)) => user,
+        Err(e) => {
+            let e = ApiError::from(e);
+            let template = UserTemplate {
+                user: String::new(),
+                profile: None,
+                error: Some(String::from(e.get_message())),
+            };
+            return (e.get_status(), template);
+        }
     };
 
-    eprintln!("{}Tags: {:?}", SERVER_HEADING, tags);
+    match get_taste_profile(state, &user).await {
+        Ok(profile) => (
+            StatusCode::OK,
+            UserTemplate {
+                user,
+                profile: Some(profile),
+                error: None,
+            },
+        ),
+        Err(e) => (
+            e.get_status(),
+            UserTemplate {
+                user,
+                profile: None,
+                error: Some(String::from(e.get_message())),
+            },
+        ),
+    }
+}
+
+// Make the taste profile of a user, given by username or uid.
+// This finds similar users, so it is run through the work pool.
+async fn get_taste_profile(state: AppState, user: &str) -> Result<TasteProfile, ApiError> {
+    let uid = get_uid(state.get_recommender()?, user)?;
 
-    let bans: Vec<u64> = if let Some(ban_string) = ban_param {
-        urlencoding::decode(ban_string)
-            .unwrap()
-            .split_whitespace()
-            .map(|ban| ban.parse().unwrap())
-            .collect()
-    } else {
-        Vec::new()
+    let pool = Arc::clone(&state.pool);
+    pool.run(move || {
+        state
+            .get_recommender()?
+            .get_taste_profile(uid)
+            .inspect_err(log_recommender_error)
+            .map_err(ApiError::from)
+    })
+    .await
+}
+
+/// Returns how the taste of two users compares
+pub async fn get_comparison(
+    State(state): State<AppState>,
+    query: Result<Query<CompareQuery>, QueryRejection>,
+) -> Result<Json<CompareResponse>, ApiError> {
+    let Query(params) = query?;
+
+    Ok(Json(compare_users(state, &params).await?))
+}
+
+#[derive(Template)]
+#[template(path = "compare.html")]
+pub struct CompareTemplate {
+    /// The usernames or uids which were asked for
+    a: String,
+    b: String,
+    comparison: Option<CompareResponse>,
+    error: Option<String>,
+}
+
+/// Display how the taste of two users compares, with a form to choose them
+pub async fn compare_page(
+    State(state): State<AppState>,
+    query: Result<Query<CompareQuery>, QueryRejection>,
+) -> (StatusCode, CompareTemplate) {
+    let mut template = CompareTemplate {
+        a: String::new(),
+        b: String::new(),
+        comparison: None,
+        error: None,
+    };
+
+    let params = match query {
+        Ok(Query(params)) => params,
+        Err(e) => {
+            let e = ApiError::from(e);
+            template.error = Some(String::from(e.get_message()));
+            return (e.get_status(), template);
+        }
     };
 
-    eprintln!("{}Bans: {:?}", SERVER_HEADING, bans);
+    template.a = params.a.clone().unwrap_or_default();
+    template.b = params.b.clone().unwrap_or_default();
 
-    let recs = match || -> Result<_, RecommenderError> {
-        Ok(recommender
-            .get_recommendations_by_uid(uid, tags, bans)?
-            .collect()?)
-    }() {
-        Ok(df) => df,
+    // Nothing is shown until both users are given, so that the page can start as a blank form
+    if template.a.trim().is_empty() || template.b.trim().is_empty() {
+        return (StatusCode::OK, template);
+    }
+
+    match compare_users(state, &params).await {
+        Ok(comparison) => {
+            template.comparison = Some(comparison);
+            (StatusCode::OK, template)
+        }
         Err(e) => {
-            eprintln!("{}Pass on error from recommender: {:?}", SERVER_HEADING, e);
-            return String::from(r#"{"type":"error","code":"RECOMMENDER_ERROR"}"#);
+            template.error = Some(String::from(e.get_message()));
+            (e.get_status(), template)
+        }
+    }
+}
+
+// Compare the two users of a comparison request, run through the work pool
+async fn compare_users(
+    state: AppState,
+    params: &CompareQuery,
+) -> Result<CompareResponse, ApiError> {
+    let recommender = state.get_recommender()?;
+
+    let mut uids = Vec::with_capacity(2);
+    for user in [&params.a, &params.b] {
+        match user.as_deref().map(str::trim) {
+            Some(user) if !user.is_empty() => uids.push(get_uid(recommender, user)?),
+            _ => {
+                return Err(ApiError::bad_request(
+                    "NO_USER",
+                    "Two users must be given to compare",
+                ))
+            }
         }
+    }
+
+    let (uid_a, uid_b) = (uids[0], uids[1]);
+    if uid_a == uid_b {
+        return Err(ApiError::bad_request(
+            "SAME_USER",
+            "A user cannot be compared with themself",
+        ));
+    }
+
+    let pool = Arc::clone(&state.pool);
+    pool.run(move || {
+        let recommender = state.get_recommender()?;
+        let comparison = recommender
+            .compare_users(uid_a, uid_b)
+            .inspect_err(log_recommender_error)?;
+        let pages = |pids: Vec<u64>| -> Vec<Recommendation> {
+            pids.into_iter()
+                .map(|pid| get_page(recommender, pid, None))
+                .collect()
+        };
+
+        Ok(CompareResponse {
+            a: comparison.a,
+            b: comparison.b,
+            similarity: comparison.similarity,
+            agreements: comparison.agreements,
+            disagreements: comparison.disagreements,
+            both_liked: pages(comparison.both_liked),
+            both_disliked: pages(comparison.both_disliked),
+            a_liked_b_disliked: pages(comparison.a_liked_b_disliked),
+            b_liked_a_disliked: pages(comparison.b_liked_a_disliked),
+            shared_tags: comparison.shared_tags,
+            liked_by_a_unread_by_b: pages(comparison.liked_by_a_unread_by_b),
+            liked_by_b_unread_by_a: pages(comparison.liked_by_b_unread_by_a),
+        })
+    })
+    .await
+}
+
+/// Returns the users whose names or url slugs best match a search
+pub async fn search_users(
+    State(state): State<AppState>,
+    query: Result<Query<SearchQuery>, QueryRejection>,
+) -> Result<Json<UserSearchResponse>, ApiError> {
+    let Query(params) = query?;
+    let recommender = state.get_recommender()?;
+    let (search, limit) = get_search(&params)?;
+
+    Ok(Json(UserSearchResponse {
+        users: recommender.search_users(search, limit),
+    }))
+}
+
+/// Returns the pages best matching a search of their names, urls, and tags
+pub async fn search_pages(
+    State(state): State<AppState>,
+    query: Result<Query<SearchQuery>, QueryRejection>,
+) -> Result<Json<PageSearchResponse>, ApiError> {
+    let Query(params) = query?;
+    let recommender = state.get_recommender()?;
+    let (search, limit) = get_search(&params)?;
+
+    Ok(Json(PageSearchResponse {
+        pages: recommender
+            .search_pages(search, limit)
+            .into_iter()
+            .map(|pid| get_page(recommender, pid, None))
+            .collect(),
+    }))
+}
+
+/// Makes an empty profile, returning the token needed to use it
+pub async fn create_profile(
+    State(state): State<AppState>,
+) -> Result<(StatusCode, Json<CreatedProfile>), ApiError> {
+    let token = state.with_profiles(|store| store.create()).await?;
+
+    Ok((StatusCode::CREATED, Json(CreatedProfile { token })))
+}
+
+/// Returns the settings saved in the profile whose token is given
+pub async fn get_profile(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<ProfileResponse>, ApiError> {
+    let token = require_token(&headers)?;
+    let profile = state.with_profiles(move |store| store.get(&token)).await?;
+
+    // Names are only for display, so they are left out until the recommender loads
+    let recommender = state.recommender.get();
+    let marks = profile
+        .marks
+        .into_iter()
+        .map(|mark| MarkedPage {
+            pid: mark.pid,
+            kind: mark.kind,
+            name: recommender.and_then(|recommender| get_page_name(recommender, mark.pid)),
+        })
+        .collect();
+
+    Ok(Json(ProfileResponse {
+        tags: profile.tags,
+        marks,
+    }))
+}
+
+/// Deletes the profile whose token is given, along with everything saved in it
+pub async fn delete_profile(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    let token = require_token(&headers)?;
+    state
+        .with_profiles(move |store| store.delete(&token))
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Saves the tag filter used for a profile's recommendations when a request gives none
+pub async fn set_profile_tags(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Result<Json<ProfileTagsBody>, JsonRejection>,
+) -> Result<StatusCode, ApiError> {
+    let token = require_token(&headers)?;
+    let Json(body) = body?;
+
+    // Filters are checked now, so that a saved filter cannot break every later request
+    let tags = body.tags.filter(|tags| !tags.trim().is_empty());
+    if let Some(tags) = &tags {
+        state.get_recommender()?.parse_tag_filter(tags)?;
+    }
+
+    state
+        .with_profiles(move |store| store.set_tags(&token, tags.as_deref()))
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Marks a page so that it is never recommended to a profile
+pub async fn set_profile_mark(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    pid: Result<Path<u64>, PathRejection>,
+    body: Result<Json<ProfileMarkBody>, JsonRejection>,
+) -> Result<StatusCode, ApiError> {
+    let token = require_token(&headers)?;
+    let Path(pid) = pid?;
+    let Json(body) = body?;
+
+    state
+        .with_profiles(move |store| store.set_mark(&token, pid, body.kind))
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Removes a page's mark from a profile
+pub async fn remove_profile_mark(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    pid: Result<Path<u64>, PathRejection>,
+) -> Result<StatusCode, ApiError> {
+    let token = require_token(&headers)?;
+    let Path(pid) = pid?;
+
+    state
+        .with_profiles(move |store| store.remove_mark(&token, pid))
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Returns how well the ranking cache is doing
+pub async fn get_cache_stats(State(state): State<AppState>) -> Json<CacheStats> {
+    Json(state.cache.get_stats())
+}
+
+/// Returns every metric in the Prometheus text format
+pub async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(&state.cache.get_stats()),
+    )
+}
+
+// Get the window of recommendations to return, from either a cursor or an offset and limit.
+// A limit given alongside a cursor replaces the cursor's limit.
+fn get_window(params: &RecommendationQuery) -> Result<Cursor, ApiError> {
+    let mut window = match (&params.cursor, params.offset) {
+        (Some(_), Some(_)) => {
+            return Err(ApiError::bad_request(
+                "INVALID_CURSOR",
+                "A cursor and an offset cannot both be given",
+            ))
+        }
+        (Some(cursor), None) => Cursor::decode(cursor)
+            .ok_or_else(|| ApiError::bad_request("INVALID_CURSOR", "Cursor is not valid"))?,
+        (None, offset) => Cursor {
+            offset: offset.unwrap_or(0),
+            limit: DEFAULT_LIMIT,
+        },
+    };
+
+    if let Some(limit) = params.limit {
+        window.limit = limit;
+    }
+
+    if window.limit == 0 || window.limit > MAX_LIMIT {
+        return Err(ApiError::bad_request(
+            "INVALID_LIMIT",
+            format!("Limit must be from 1 to {}", MAX_LIMIT),
+        ));
+    }
+
+    Ok(window)
+}
+
+// Get the uid of a user from either their username or their uid
+fn get_uid(recommender: &Recommender, user_string: &str) -> Result<u64, ApiError> {
+    match recommender.get_user_by_username(user_string) {
+        Ok(user) => match user[2] {
+            AnyValue::UInt64(uid) => Ok(uid),
+            _ => unreachable!(),
+        },
+        // Check if this is is a raw uid as opposed to a username
+        Err(RecommenderError::UserNotFound) => user_string
+            .parse()
+            .map_err(|_| ApiError::from(RecommenderError::UserNotFound)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Get the search and number of results asked for, checking that both are usable
+fn get_search(params: &SearchQuery) -> Result<(&str, usize), ApiError> {
+    let search = match params.q.as_deref() {
+        Some(search) if !search.trim().is_empty() => search,
+        _ => return Err(ApiError::bad_request("NO_QUERY", "No search was given")),
     };
 
-    let top_recs = recs.head(Some(500));
+    let limit = params.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+    if limit == 0 || limit > MAX_SEARCH_LIMIT {
+        return Err(ApiError::bad_request(
+            "INVALID_LIMIT",
+            format!("Limit must be from 1 to {}", MAX_SEARCH_LIMIT),
+        ));
+    }
+
+    Ok((search, limit))
+}
+
+// Get the profile token from an `Authorization: Bearer <token>` header, if there is one.
+// Tokens are sent in a header rather than the url so that they are never logged.
+fn get_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| String::from(token.trim()))
+}
+
+// Get the profile token, which the request cannot be done without
+fn require_token(headers: &HeaderMap) -> Result<String, ApiError> {
+    get_token(headers).ok_or_else(|| {
+        ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "NO_PROFILE",
+            "No profile token was given",
+        )
+    })
+}
 
-    recs_to_string(&recommender, top_recs)
+// Get the name of a page, if it is known
+fn get_page_name(recommender: &Recommender, pid: u64) -> Option<String> {
+    match recommender.get_page_by_pid(pid).ok()?.first()? {
+        AnyValue::String(name) => Some(String::from(*name)),
+        _ => unreachable!(),
+    }
 }
 
-// Get a JSON encoded version of a recommendation DataFrame
-fn recs_to_string(recommender: &Recommender, full_recs: DataFrame) -> String {
-    let pages: Vec<_> = full_recs
+// Combine the tag expression and the lists of tags a page needs any of or none of into one filter
+fn get_tag_filter(
+    recommender: &Recommender,
+    params: &RecommendationQuery,
+) -> Result<TagFilter, ApiError> {
+    let mut filters = Vec::new();
+
+    let expression = recommender.parse_tag_filter(params.tags.as_deref().unwrap_or_default())?;
+    if !expression.is_empty() {
+        filters.push(expression);
+    }
+
+    let any_tags = parse_tags(recommender, params.any_tags.as_deref())?;
+    if !any_tags.is_empty() {
+        filters.push(TagFilter::Any(any_tags));
+    }
+
+    let exclude_tags = parse_tags(recommender, params.exclude_tags.as_deref())?;
+    if !exclude_tags.is_empty() {
+        filters.push(TagFilter::Not(Box::new(TagFilter::Any(exclude_tags))));
+    }
+
+    Ok(match filters.len() {
+        1 => filters.remove(0),
+        _ => TagFilter::All(filters),
+    })
+}
+
+// Parse a whitespace separated list of tag names or ids
+fn parse_tags(recommender: &Recommender, tags: Option<&str>) -> Result<Vec<TagFilter>, ApiError> {
+    tags.unwrap_or_default()
+        .split_whitespace()
+        .map(|tag| match recommender.get_tag_id(tag) {
+            Some(id) => Ok(TagFilter::Tag(id)),
+            None => Err(ApiError::bad_request(
+                "INVALID_TAGS",
+                format!("Unknown tag: {}", tag),
+            )),
+        })
+        .collect()
+}
+
+// Parse a whitespace separated list of ids, returning the first invalid one on failure
+fn parse_ids<T: FromStr>(ids: Option<&str>) -> Result<Vec<T>, String> {
+    ids.unwrap_or_default()
+        .split_whitespace()
+        .map(|id| id.parse().map_err(|_| String::from(id)))
+        .collect()
+}
+
+// Get a serializable version of a recommendation DataFrame, with explanations for each
+// recommendation if they were requested
+fn recs_to_vec(
+    recommender: &Recommender,
+    full_recs: DataFrame,
+    explanations: Option<Vec<Explanation>>,
+) -> Vec<Recommendation> {
+    let mut explanations = explanations.map(|explanations| explanations.into_iter());
+
+    full_recs
         .column("pid")
         .expect("pid column should always exist")
         .u64()
         .expect("pids should all be u64")
         .iter()
-        .map(
-            |pid| match recommender.get_page_by_pid(pid.expect("pids should all be Some")) {
-                Ok(vec) => Recommendation {
-                    name: match &vec[0] {
-                        AnyValue::String(page_name) => String::from(*page_name),
-                        _ => unreachable!(),
-                    },
-                    url: match &vec[1] {
-                        AnyValue::String(page_url) => String::from(*page_url),
-                        _ => unreachable!(),
-                    },
-                    pid: match &vec[2] {
-                        AnyValue::UInt64(page_id) => *page_id,
-                        _ => unreachable!(),
-                    },
-                    tags: match &vec[3] {
-                        AnyValue::List(page_tags) => page_tags
-                            .u16()
-                            .expect("Tags should all be u16")
-                            .iter()
-                            .map(|tag| {
-                                recommender
-                                    .get_tag_by_id(tag.expect("Tags should all be Some"))
-                                    .unwrap()
-                            })
-                            .collect(),
-                        _ => unreachable!(),
-                    },
-                },
-                Err(e) => {
-                    panic!(
-                        "{}Page in recommender but not pages list: {:?}",
-                        SERVER_HEADING, e
-                    );
-                }
-            },
-        )
-        .collect();
+        .map(|pid| {
+            get_page(
+                recommender,
+                pid.expect("pids should all be Some"),
+                explanations
+                    .as_mut()
+                    .and_then(|explanations| explanations.next()),
+            )
+        })
+        .collect()
+}
 
-    serde_json::to_string(&pages).expect("Page vec should be serializeable")
+// Get a serializable version of a page
+fn get_page(
+    recommender: &Recommender,
+    pid: u64,
+    explanation: Option<Explanation>,
+) -> Recommendation {
+    match recommender.get_page_by_pid(pid) {
+        Ok(vec) => Recommendation {
+            name: match &vec[0] {
+                AnyValue::String(page_name) => String::from(*page_name),
+                _ => unreachable!(),
+            },
+            url: match &vec[1] {
+                AnyValue::String(page_url) => String::from(*page_url),
+                _ => unreachable!(),
+            },
+            pid: match &vec[2] {
+                AnyValue::UInt64(page_id) => *page_id,
+                _ => unreachable!(),
+            },
+            tags: match &vec[3] {
+                AnyValue::List(page_tags) => page_tags
+                    .u16()
+                    .expect("Tags should all be u16")
+                    .iter()
+                    .map(|tag| {
+                        recommender
+                            .get_tag_by_id(tag.expect("Tags should all be Some"))
+                            .unwrap()
+                    })
+                    .collect(),
+                _ => unreachable!(),
+            },
+            explanation,
+        },
+        Err(e) => {
+            panic!("Page in recommender but not pages list: {:?}", e);
+        }
+    }
 }
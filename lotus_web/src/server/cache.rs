@@ -0,0 +1,260 @@
+use crate::recommender::{LocalRating, Ranking, Recommender, RecommenderError, TagFilter};
+use lru::LruCache;
+use serde::Serialize;
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Identifies a cached ranking.
+/// Rankings depend on the user, any ratings they gave in LOTUS, the tags the ranked pages were
+/// limited to, and the options of the recommender which made them.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct RankingKey {
+    pub uid: u64,
+    /// Hash of the user's local ratings, ordered by pid
+    pub ratings_hash: u64,
+    /// Hash of the tag filter only matching pages were ranked with, which is the empty filter
+    /// for a ranking of every page
+    pub tags_hash: u64,
+    pub engine_hash: u64,
+}
+
+impl RankingKey {
+    /// Make the key for a ranking of the pages passing a tag filter, for a user with the given
+    /// local ratings, made by a recommender with the given engine hash.
+    /// Ratings are hashed in pid order, so the order they were given in does not matter.
+    pub fn new(
+        uid: u64,
+        ratings: &[LocalRating],
+        tags: &TagFilter,
+        engine_hash: u64,
+    ) -> RankingKey {
+        let mut ratings = ratings.to_vec();
+        ratings.sort_unstable();
+
+        RankingKey {
+            uid,
+            ratings_hash: hash(&ratings),
+            tags_hash: hash(tags),
+            engine_hash,
+        }
+    }
+
+    /// Returns the key of the same ranking, but of every page rather than only some tags
+    pub fn for_every_page(&self) -> RankingKey {
+        RankingKey {
+            tags_hash: hash(&TagFilter::default()),
+            ..*self
+        }
+    }
+}
+
+/// Counters describing how well the cache is doing
+#[derive(Debug, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub capacity: usize,
+}
+
+/// Least recently used cache of rankings.
+/// Requests which only change bans or the window of results reuse the same ranking, and a ranking
+/// of every page is reused for any tags.
+/// The recommender is only loaded once, so cached rankings never go out of date. New data means
+/// restarting the server, which starts with an empty cache.
+pub struct RankingCache {
+    rankings: Mutex<LruCache<RankingKey, Arc<Ranking>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl RankingCache {
+    /// Creates a cache holding at most the given number of rankings.
+    /// A capacity of 0 is treated as 1.
+    pub fn new(capacity: usize) -> RankingCache {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+
+        RankingCache {
+            rankings: Mutex::new(LruCache::new(capacity)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Get a ranking for a user with the given local ratings which includes every page passing the
+    /// given tag filter, making it with the recommender if it is not cached. Also returns whether
+    /// the ranking was already cached.
+    /// A cached ranking of every page is used if there is one. Otherwise only pages passing the
+    /// filter are ranked, so the ranking may contain no other pages and must still be filtered.
+    pub fn get_ranking(
+        &self,
+        recommender: &Recommender,
+        uid: u64,
+        ratings: &[LocalRating],
+        tags: &TagFilter,
+    ) -> Result<(Arc<Ranking>, bool), RecommenderError> {
+        let key = RankingKey::new(uid, ratings, tags, recommender.get_engine_hash());
+
+        self.get_or_rank(key, || recommender.get_ranking_for_tags(uid, ratings, tags))
+    }
+
+    // Get the ranking with the given key, or the ranking of every page for the same user if there
+    // is one, making it with the given function otherwise
+    fn get_or_rank(
+        &self,
+        key: RankingKey,
+        rank: impl FnOnce() -> Result<Ranking, RecommenderError>,
+    ) -> Result<(Arc<Ranking>, bool), RecommenderError> {
+        {
+            let mut rankings = self.lock();
+            for key in [key.for_every_page(), key] {
+                if let Some(ranking) = rankings.get(&key) {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok((Arc::clone(ranking), true));
+                }
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        // The lock is not held while ranking, so a slow ranking does not block other users.
+        // Two requests for the same user at once may both rank, which is harmless.
+        let ranking = Arc::new(rank()?);
+        self.lock().put(key, Arc::clone(&ranking));
+
+        Ok((ranking, false))
+    }
+
+    pub fn get_stats(&self) -> CacheStats {
+        let rankings = self.lock();
+
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: rankings.len(),
+            capacity: rankings.cap().get(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, LruCache<RankingKey, Arc<Ranking>>> {
+        // A panic while holding the lock cannot leave the cache in a broken state
+        self.rankings
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(uid: u64) -> RankingKey {
+        RankingKey::new(uid, &[], &TagFilter::default(), 0)
+    }
+
+    // Get a ranking from the cache, returning whether it was cached
+    fn get(cache: &RankingCache, key: RankingKey) -> bool {
+        let (ranking, cached) = cache
+            .get_or_rank(key, || Ok(Ranking::empty(key.uid)))
+            .expect("Ranking not made");
+        assert_eq!(ranking.get_uid(), key.uid);
+
+        cached
+    }
+
+    #[test]
+    fn count_hits_and_misses() {
+        let cache = RankingCache::new(4);
+
+        assert!(!get(&cache, key(1)));
+        assert!(get(&cache, key(1)));
+        assert!(!get(&cache, key(2)));
+        assert!(get(&cache, key(1)));
+
+        let stats = cache.get_stats();
+        assert_eq!((stats.hits, stats.misses), (2, 2));
+        assert_eq!((stats.entries, stats.capacity), (2, 4));
+
+        // Failed rankings are counted, but not cached
+        assert!(cache
+            .get_or_rank(key(3), || Err(RecommenderError::UserNotFound))
+            .is_err());
+        assert_eq!(cache.get_stats().entries, 2);
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let cache = RankingCache::new(2);
+
+        get(&cache, key(1));
+        get(&cache, key(2));
+        // Using the first ranking makes the second the least recently used
+        get(&cache, key(1));
+        get(&cache, key(3));
+
+        assert!(get(&cache, key(1)));
+        assert!(get(&cache, key(3)));
+        assert!(!get(&cache, key(2)));
+        assert_eq!(cache.get_stats().entries, 2);
+    }
+
+    #[test]
+    fn separate_keys() {
+        let upvote = LocalRating {
+            pid: 1,
+            upvote: true,
+        };
+        let downvote = LocalRating {
+            pid: 2,
+            upvote: false,
+        };
+        let tags = TagFilter::Tag(0);
+
+        let base = RankingKey::new(1, &[upvote, downvote], &TagFilter::default(), 0);
+        assert_eq!(
+            base,
+            RankingKey::new(1, &[downvote, upvote], &TagFilter::default(), 0)
+        );
+        assert_ne!(
+            base,
+            RankingKey::new(1, &[upvote], &TagFilter::default(), 0)
+        );
+        assert_ne!(
+            base,
+            RankingKey::new(1, &[upvote, downvote], &TagFilter::default(), 1)
+        );
+
+        let tagged = RankingKey::new(1, &[upvote, downvote], &tags, 0);
+        assert_ne!(base, tagged);
+        assert_eq!(base, tagged.for_every_page());
+
+        let cache = RankingCache::new(8);
+        assert!(!get(&cache, base));
+        assert!(!get(
+            &cache,
+            RankingKey::new(1, &[upvote], &TagFilter::default(), 0)
+        ));
+        assert!(!get(
+            &cache,
+            RankingKey::new(1, &[upvote, downvote], &TagFilter::default(), 1)
+        ));
+
+        // A ranking of only some tags is not used for every page, but the reverse is
+        let other_tagged = RankingKey::new(2, &[], &tags, 0);
+        assert!(!get(&cache, other_tagged));
+        assert!(!get(&cache, other_tagged.for_every_page()));
+        assert!(get(&cache, tagged));
+    }
+}
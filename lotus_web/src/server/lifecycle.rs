@@ -0,0 +1,122 @@
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    env, io,
+    net::{SocketAddr, TcpListener as StdListener, ToSocketAddrs},
+    os::fd::FromRawFd,
+    process,
+};
+use tokio::{
+    net::TcpListener,
+    signal::{self, unix::SignalKind},
+};
+use tracing::info;
+
+/// First file descriptor passed by systemd socket activation. 0 to 2 are the standard streams.
+const LISTEN_FDS_START: i32 = 3;
+
+/// Connections which can wait to be accepted before more are refused
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Get the listener to serve on.
+/// If systemd passed a socket to this process, given as `activated`, that is used and the address
+/// is ignored. Otherwise, the address is bound. With `reuse_port`, other processes may bind the
+/// same address at the same time, so a new server can start listening before the old one stops.
+pub fn get_listener(
+    address: &str,
+    reuse_port: bool,
+    activated: Option<StdListener>,
+) -> io::Result<TcpListener> {
+    let listener = match activated {
+        Some(listener) => {
+            info!("Using socket passed by systemd");
+            listener
+        }
+        None => bind(address, reuse_port)?,
+    };
+
+    // Tokio requires non-blocking sockets, and systemd does not promise them
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener)
+}
+
+/// Wait until the server is asked to stop, by either SIGTERM or SIGINT (Ctrl+C)
+pub async fn shutdown_signal() {
+    let interrupt = async {
+        signal::ctrl_c()
+            .await
+            .expect("SIGINT handler should always be installable");
+    };
+
+    let terminate = async {
+        signal::unix::signal(SignalKind::terminate())
+            .expect("SIGTERM handler should always be installable")
+            .recv()
+            .await;
+    };
+
+    tokio::select! {
+        _ = interrupt => info!("SIGINT received, finishing requests before stopping"),
+        _ = terminate => info!("SIGTERM received, finishing requests before stopping"),
+    }
+}
+
+/// Take the first socket passed by systemd socket activation, if there is one.
+/// See sd_listen_fds(3) for how sockets are passed.
+///
+/// This removes the variables systemd set, and changing the environment while another thread
+/// might read it is undefined behaviour. It must be called before any other thread is started,
+/// including the async runtime.
+pub fn take_activated_listener() -> Option<StdListener> {
+    let listen_pid: u32 = env::var("LISTEN_PID").ok()?.parse().ok()?;
+    let listen_fds: i32 = env::var("LISTEN_FDS").ok()?.parse().ok()?;
+
+    // The variables are inherited, so they may have been meant for a parent process
+    if listen_pid != process::id() || listen_fds < 1 {
+        return None;
+    }
+
+    // Nothing started by this process should think the socket is theirs
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    // SAFETY: systemd passed this descriptor to this process for it to own, and the variables
+    // were removed so it cannot be taken twice
+    Some(unsafe { StdListener::from_raw_fd(LISTEN_FDS_START) })
+}
+
+fn bind(address: &str, reuse_port: bool) -> io::Result<StdListener> {
+    let address: SocketAddr = address.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Address could not be resolved: {}", address),
+        )
+    })?;
+
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(reuse_port)?;
+    socket.bind(&address.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn share_port() {
+        let first = get_listener("127.0.0.1:0", true, None).expect("First listener not bound");
+        let address = first.local_addr().unwrap().to_string();
+
+        // A replacement server must be able to bind while the old one is still listening
+        assert!(get_listener(&address, true, None).is_ok());
+        assert!(get_listener(&address, false, None).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use crate::recommender::Recommender;
+use axum::{
+    body::Body,
+    http::{
+        header::{self, HeaderMap, HeaderValue},
+        StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// How long clients may reuse a list before checking whether it changed.
+/// Lists only change when the server restarts with new data.
+const LIST_CACHE_CONTROL: &str = "public, max-age=300";
+
+/// A JSON body which never changes while the server runs.
+/// It is serialized once, and tagged so that clients which already have it are sent nothing.
+pub struct CachedJson {
+    body: String,
+    etag: HeaderValue,
+}
+
+impl CachedJson {
+    pub fn new<T: Serialize + ?Sized>(value: &T) -> CachedJson {
+        let body = serde_json::to_string(value).expect("Lists should always be serializable");
+
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+        let etag = HeaderValue::from_str(&format!("\"{:016x}\"", hasher.finish()))
+            .expect("Hex ETags should always be valid headers");
+
+        CachedJson { body, etag }
+    }
+
+    /// Respond with the body, or with 304 Not Modified if the request already has this version
+    pub fn respond(&self, request_headers: &HeaderMap) -> Response {
+        let headers = [
+            (header::ETAG, self.etag.clone()),
+            (
+                header::CACHE_CONTROL,
+                HeaderValue::from_static(LIST_CACHE_CONTROL),
+            ),
+        ];
+
+        if self.matches(request_headers) {
+            return (StatusCode::NOT_MODIFIED, headers).into_response();
+        }
+
+        (
+            headers,
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            )],
+            Body::from(self.body.clone()),
+        )
+            .into_response()
+    }
+
+    // Whether any of the ETags in If-None-Match are this one
+    fn matches(&self, request_headers: &HeaderMap) -> bool {
+        request_headers
+            .get_all(header::IF_NONE_MATCH)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag.as_bytes() == self.etag.as_bytes())
+    }
+}
+
+/// Lists the front end needs to search and filter, made from the recommender once it loads
+pub struct Lists {
+    pub tags: CachedJson,
+    pub users: CachedJson,
+}
+
+impl Lists {
+    pub fn new(recommender: &Recommender) -> Lists {
+        Lists {
+            tags: CachedJson::new(&recommender.get_tags()),
+            users: CachedJson::new(&recommender.get_users_list()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn not_modified_when_tag_matches() {
+        let json = CachedJson::new(&["scp", "tale"]);
+
+        let response = json.respond(&HeaderMap::new());
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG].clone();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag);
+        assert_eq!(json.respond(&headers).status(), StatusCode::NOT_MODIFIED);
+
+        headers.insert(
+            header::IF_NONE_MATCH,
+            HeaderValue::from_static("\"0000000000000000\""),
+        );
+        assert_eq!(json.respond(&headers).status(), StatusCode::OK);
+
+        // Lists with different contents must never share a tag
+        assert_ne!(json.etag, CachedJson::new(&["scp"]).etag);
+    }
+}
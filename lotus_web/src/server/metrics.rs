@@ -0,0 +1,226 @@
+use super::{AppState, CacheStats};
+use crate::recommender::ModelSize;
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Buckets for recommendation phases, which range from milliseconds to many seconds
+const PHASE_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Everything exposed on the metrics endpoint, in the Prometheus text format
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    phase_duration: HistogramVec,
+    cache_hits: IntCounter,
+    cache_misses: IntCounter,
+    cache_entries: IntGauge,
+    model_users: IntGauge,
+    model_pages: IntGauge,
+    model_votes: IntGauge,
+    scrape_timestamp: Gauge,
+    scrape_age: Gauge,
+    /// When the scrape the recommender's data came from started, once it is loaded
+    scraped_at: OnceLock<SystemTime>,
+    /// Held while rendering, so that concurrent scrapes do not both catch up the cache counters
+    render_lock: Mutex<()>,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Metrics, prometheus::Error> {
+        let registry = Registry::new_custom(Some(String::from("lotus")), None)?;
+
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["route", "status"],
+        )?;
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to respond to HTTP requests",
+            ),
+            &["route"],
+        )?;
+        let phase_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "recommendation_phase_duration_seconds",
+                "Time taken by each phase of making recommendations",
+            )
+            .buckets(PHASE_BUCKETS.to_vec()),
+            &["phase"],
+        )?;
+        let cache_hits = IntCounter::new("cache_hits_total", "Rankings found in the cache")?;
+        let cache_misses = IntCounter::new("cache_misses_total", "Rankings not in the cache")?;
+        let cache_entries = IntGauge::new("cache_entries", "Rankings held in the cache")?;
+        let model_users = IntGauge::new("model_users", "Users in the recommender")?;
+        let model_pages = IntGauge::new("model_pages", "Pages in the recommender")?;
+        let model_votes = IntGauge::new("model_votes", "Votes the recommender was built from")?;
+        let scrape_timestamp = Gauge::new(
+            "scrape_timestamp_seconds",
+            "Unix time the scrape the recommender's data came from started",
+        )?;
+        let scrape_age = Gauge::new(
+            "scrape_age_seconds",
+            "Seconds since the scrape the recommender's data came from started",
+        )?;
+
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(request_duration.clone()))?;
+        registry.register(Box::new(phase_duration.clone()))?;
+        registry.register(Box::new(cache_hits.clone()))?;
+        registry.register(Box::new(cache_misses.clone()))?;
+        registry.register(Box::new(cache_entries.clone()))?;
+        registry.register(Box::new(model_users.clone()))?;
+        registry.register(Box::new(model_pages.clone()))?;
+        registry.register(Box::new(model_votes.clone()))?;
+        registry.register(Box::new(scrape_timestamp.clone()))?;
+        registry.register(Box::new(scrape_age.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            requests,
+            request_duration,
+            phase_duration,
+            cache_hits,
+            cache_misses,
+            cache_entries,
+            model_users,
+            model_pages,
+            model_votes,
+            scrape_timestamp,
+            scrape_age,
+            scraped_at: OnceLock::new(),
+            render_lock: Mutex::new(()),
+        })
+    }
+
+    /// Record the size of the recommender being served
+    pub fn set_model_size(&self, size: ModelSize) {
+        self.model_users.set(size.users as i64);
+        self.model_pages.set(size.pages as i64);
+        self.model_votes.set(size.votes as i64);
+    }
+
+    /// Record when the scrape the recommender's data came from started, as a unix timestamp
+    pub fn set_scraped_at(&self, scraped_at: u64) {
+        self.scrape_timestamp.set(scraped_at as f64);
+        // The recommender is only loaded once, so this is never set twice
+        let _ = self
+            .scraped_at
+            .set(UNIX_EPOCH + Duration::from_secs(scraped_at));
+    }
+
+    pub fn observe_request(&self, route: &str, status: u16, duration: Duration) {
+        self.requests
+            .with_label_values(&[route, &status.to_string()])
+            .inc();
+        self.request_duration
+            .with_label_values(&[route])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Record the time taken by one phase of making recommendations
+    pub fn observe_phase(&self, phase: &str, duration: Duration) {
+        self.phase_duration
+            .with_label_values(&[phase])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Get every metric in the Prometheus text format
+    pub fn render(&self, cache: &CacheStats) -> String {
+        let _guard = self
+            .render_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        // The cache keeps its own counters, so these only catch up to them
+        self.cache_hits
+            .inc_by(cache.hits.saturating_sub(self.cache_hits.get()));
+        self.cache_misses
+            .inc_by(cache.misses.saturating_sub(self.cache_misses.get()));
+        self.cache_entries.set(cache.entries as i64);
+
+        if let Some(age) = self
+            .scraped_at
+            .get()
+            .and_then(|scraped_at| scraped_at.elapsed().ok())
+        {
+            self.scrape_age.set(age.as_secs_f64());
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Metrics should always be encodable");
+
+        String::from_utf8(buffer).expect("Metrics text should always be UTF-8")
+    }
+}
+
+/// Middleware which counts and times every request by the route it matched
+pub async fn track_requests(
+    State(state): State<AppState>,
+    matched_path: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = match &matched_path {
+        Some(path) => path.as_str(),
+        // Static files are served by a nested service, which does not give a matched path.
+        // They are all counted together, rather than once per file
+        None if request.uri().path().starts_with("/files/") => "/files",
+        None => "unmatched",
+    }
+    .to_owned();
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+
+    state
+        .metrics
+        .observe_request(&route, response.status().as_u16(), start.elapsed());
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_metrics() {
+        let metrics = Metrics::new().expect("Metrics not created");
+        metrics.observe_request("/api/v1/recommendations", 200, Duration::from_millis(20));
+        metrics.observe_phase("similarity", Duration::from_millis(5));
+
+        let cache = CacheStats {
+            hits: 3,
+            misses: 1,
+            entries: 1,
+            capacity: 8,
+        };
+        let text = metrics.render(&cache);
+
+        assert!(text.contains(
+            r#"lotus_http_requests_total{route="/api/v1/recommendations",status="200"} 1"#
+        ));
+        assert!(text.contains("lotus_cache_hits_total 3"));
+
+        // Rendering again must not count the same hits twice
+        let text = metrics.render(&cache);
+        assert!(text.contains("lotus_cache_hits_total 3"));
+    }
+}
@@ -0,0 +1,344 @@
+use crate::recommender::LocalRating;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Debug,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+/// Most pages one profile can mark, so that a single profile cannot fill the database
+pub const MAX_MARKS: usize = 10000;
+
+/// Tables made when a database is first opened.
+/// Marks are deleted along with their profile.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS profiles (
+        id INTEGER PRIMARY KEY,
+        token TEXT NOT NULL UNIQUE,
+        tags TEXT,
+        created INTEGER NOT NULL,
+        updated INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS marks (
+        profile INTEGER NOT NULL REFERENCES profiles (id) ON DELETE CASCADE,
+        pid INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        PRIMARY KEY (profile, pid)
+    ) WITHOUT ROWID;
+";
+
+/// Why a page is kept out of a profile's recommendations.
+/// Upvotes and downvotes are also used as ratings, like votes on the wiki which only LOTUS sees.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkKind {
+    Ban,
+    NotInterested,
+    Read,
+    Upvote,
+    Downvote,
+}
+
+impl MarkKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MarkKind::Ban => "ban",
+            MarkKind::NotInterested => "not_interested",
+            MarkKind::Read => "read",
+            MarkKind::Upvote => "upvote",
+            MarkKind::Downvote => "downvote",
+        }
+    }
+
+    fn parse(kind: &str) -> Option<MarkKind> {
+        match kind {
+            "ban" => Some(MarkKind::Ban),
+            "not_interested" => Some(MarkKind::NotInterested),
+            "read" => Some(MarkKind::Read),
+            "upvote" => Some(MarkKind::Upvote),
+            "downvote" => Some(MarkKind::Downvote),
+            _ => None,
+        }
+    }
+}
+
+/// A page marked by a profile
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mark {
+    pub pid: u64,
+    pub kind: MarkKind,
+}
+
+/// Settings saved for one user of the site
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Tag filter expression to use when a request does not give one
+    pub tags: Option<String>,
+    /// Pages which are never recommended, ordered by pid
+    pub marks: Vec<Mark>,
+}
+
+impl Profile {
+    /// The pids of every marked page
+    pub fn get_marked_pids(&self) -> Vec<u64> {
+        self.marks.iter().map(|mark| mark.pid).collect()
+    }
+
+    /// The upvoted and downvoted pages, to be added to the user's votes
+    pub fn get_ratings(&self) -> Vec<LocalRating> {
+        self.marks
+            .iter()
+            .filter_map(|mark| {
+                match mark.kind {
+                    MarkKind::Upvote => Some(true),
+                    MarkKind::Downvote => Some(false),
+                    _ => None,
+                }
+                .map(|upvote| LocalRating {
+                    pid: mark.pid,
+                    upvote,
+                })
+            })
+            .collect()
+    }
+}
+
+pub enum ProfileError {
+    Database(rusqlite::Error),
+    /// No profile has the given token
+    NotFound,
+    /// The profile already has `MAX_MARKS` marks
+    TooManyMarks,
+}
+
+impl Debug for ProfileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            Self::Database(err) => format!("Database: {:?}", err),
+            Self::NotFound => String::from("Profile not found"),
+            Self::TooManyMarks => String::from("Too many marks"),
+        };
+
+        write!(f, "{}", message)
+    }
+}
+
+impl From<rusqlite::Error> for ProfileError {
+    fn from(value: rusqlite::Error) -> Self {
+        ProfileError::Database(value)
+    }
+}
+
+/// Profiles kept in a SQLite database, each found by a random token given when it is made.
+/// The token is the only thing needed to read or change a profile, so it should be kept secret.
+pub struct ProfileStore {
+    /// SQLite connections cannot be shared between threads, so requests take turns.
+    /// Every query is small, so this is never held for long
+    connection: Mutex<Connection>,
+}
+
+impl ProfileStore {
+    /// Open the database at the given path, creating it if it does not exist
+    pub fn open(path: &str) -> Result<ProfileStore, ProfileError> {
+        let connection = Connection::open(path)?;
+        ProfileStore::from_connection(connection)
+    }
+
+    fn from_connection(connection: Connection) -> Result<ProfileStore, ProfileError> {
+        connection.pragma_update(None, "foreign_keys", true)?;
+        // Lets profiles be read while another is being written
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(ProfileStore {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Make an empty profile, returning its token
+    pub fn create(&self) -> Result<String, ProfileError> {
+        let token = Uuid::new_v4().simple().to_string();
+        let now = unix_time();
+
+        self.lock().execute(
+            "INSERT INTO profiles (token, created, updated) VALUES (?1, ?2, ?2)",
+            params![token, now],
+        )?;
+
+        Ok(token)
+    }
+
+    pub fn get(&self, token: &str) -> Result<Profile, ProfileError> {
+        let connection = self.lock();
+        let (id, tags) = connection
+            .query_row(
+                "SELECT id, tags FROM profiles WHERE token = ?1",
+                [token],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<String>>(1)?)),
+            )
+            .optional()?
+            .ok_or(ProfileError::NotFound)?;
+
+        let mut statement = connection
+            .prepare_cached("SELECT pid, kind FROM marks WHERE profile = ?1 ORDER BY pid")?;
+        let marks = statement
+            .query_map([id], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .filter_map(|mark| match mark {
+                // Kinds are only ever written by this, so unknown ones are from a newer version
+                Ok((pid, kind)) => MarkKind::parse(&kind).map(|kind| {
+                    Ok(Mark {
+                        pid: pid as u64,
+                        kind,
+                    })
+                }),
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<Result<Vec<Mark>, rusqlite::Error>>()?;
+
+        Ok(Profile { tags, marks })
+    }
+
+    pub fn delete(&self, token: &str) -> Result<(), ProfileError> {
+        match self
+            .lock()
+            .execute("DELETE FROM profiles WHERE token = ?1", [token])?
+        {
+            0 => Err(ProfileError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// Save the tag filter used when a request does not give one, or remove it with None.
+    /// The filter should already have been checked.
+    pub fn set_tags(&self, token: &str, tags: Option<&str>) -> Result<(), ProfileError> {
+        match self.lock().execute(
+            "UPDATE profiles SET tags = ?2, updated = ?3 WHERE token = ?1",
+            params![token, tags, unix_time()],
+        )? {
+            0 => Err(ProfileError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// Mark a page, replacing any mark it already had
+    pub fn set_mark(&self, token: &str, pid: u64, kind: MarkKind) -> Result<(), ProfileError> {
+        let mut connection = self.lock();
+        let transaction = connection.transaction()?;
+        let id = touch(&transaction, token)?;
+
+        let marks: i64 = transaction.query_row(
+            "SELECT COUNT(*) FROM marks WHERE profile = ?1 AND pid != ?2",
+            params![id, pid as i64],
+            |row| row.get(0),
+        )?;
+        if marks as usize >= MAX_MARKS {
+            return Err(ProfileError::TooManyMarks);
+        }
+
+        transaction.execute(
+            "INSERT OR REPLACE INTO marks (profile, pid, kind) VALUES (?1, ?2, ?3)",
+            params![id, pid as i64, kind.as_str()],
+        )?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Remove a page's mark. Pages which were not marked are ignored
+    pub fn remove_mark(&self, token: &str, pid: u64) -> Result<(), ProfileError> {
+        let mut connection = self.lock();
+        let transaction = connection.transaction()?;
+        let id = touch(&transaction, token)?;
+
+        transaction.execute(
+            "DELETE FROM marks WHERE profile = ?1 AND pid = ?2",
+            params![id, pid as i64],
+        )?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Connection> {
+        // A panic while holding the lock cannot leave a transaction open, since it is rolled
+        // back when dropped
+        self.connection
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// Record that a profile was changed, returning its id
+fn touch(connection: &Connection, token: &str) -> Result<i64, ProfileError> {
+    connection
+        .query_row(
+            "UPDATE profiles SET updated = ?2 WHERE token = ?1 RETURNING id",
+            params![token, unix_time()],
+            |row| row.get(0),
+        )
+        .optional()?
+        .ok_or(ProfileError::NotFound)
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_round_trip() {
+        let store = ProfileStore::from_connection(Connection::open_in_memory().unwrap())
+            .expect("Store not opened");
+        let token = store.create().expect("Profile not made");
+
+        store.set_tags(&token, Some("scp NOT joke")).unwrap();
+        store.set_mark(&token, 173, MarkKind::Ban).unwrap();
+        store.set_mark(&token, 96, MarkKind::Read).unwrap();
+        store.set_mark(&token, 49, MarkKind::Downvote).unwrap();
+        // Marking again changes the kind rather than adding another mark
+        store
+            .set_mark(&token, 173, MarkKind::NotInterested)
+            .unwrap();
+        store.remove_mark(&token, 96).unwrap();
+
+        let profile = store.get(&token).expect("Profile not found");
+        assert_eq!(profile.tags.as_deref(), Some("scp NOT joke"));
+        assert_eq!(
+            profile.marks,
+            vec![
+                Mark {
+                    pid: 49,
+                    kind: MarkKind::Downvote
+                },
+                Mark {
+                    pid: 173,
+                    kind: MarkKind::NotInterested
+                }
+            ]
+        );
+        assert_eq!(
+            profile.get_ratings(),
+            vec![LocalRating {
+                pid: 49,
+                upvote: false
+            }]
+        );
+
+        store.delete(&token).unwrap();
+        assert!(matches!(store.get(&token), Err(ProfileError::NotFound)));
+        assert!(matches!(
+            store.set_mark("not a token", 1, MarkKind::Ban),
+            Err(ProfileError::NotFound)
+        ));
+    }
+}
@@ -0,0 +1,505 @@
+use super::profiles::{MarkKind, ProfileError};
+use crate::recommender::{Explanation, GroupStrategy, ModelSize, RecommenderError, UserMatch};
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::{fmt::Display, str::FromStr};
+
+/// Query parameters accepted by the recommendation endpoints
+#[derive(Debug, Deserialize)]
+pub struct RecommendationQuery {
+    /// Username or uid of the user to recommend for
+    pub user: Option<String>,
+    /// Tag filter expression every recommendation must pass, like
+    /// `scp AND (horror OR cosmic) AND NOT joke`. A space separated list requires every tag
+    pub tags: Option<String>,
+    /// Space separated tags, at least one of which every recommendation must have
+    pub any_tags: Option<String>,
+    /// Space separated tags which no recommendation may have
+    pub exclude_tags: Option<String>,
+    /// Space separated pids which will never be recommended
+    pub bans: Option<String>,
+    /// Space separated pids the user liked, which are used like upvotes and never recommended
+    pub likes: Option<String>,
+    /// Space separated pids the user disliked, which are used like downvotes and never recommended
+    pub dislikes: Option<String>,
+    /// How much to favor dissimilar pages, from 0 to 1
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub diversity: Option<f64>,
+    /// How much to discount popular pages. Defaults to the server's setting
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub novelty: Option<f64>,
+    /// Whether to explain each recommendation
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub explain: Option<bool>,
+    /// Number of recommendations to skip
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub offset: Option<usize>,
+    /// Maximum number of recommendations to return
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub limit: Option<usize>,
+    /// Cursor from a previous response, in place of an offset
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub cursor: Option<String>,
+}
+
+/// Query parameters accepted by the group recommendation endpoints.
+/// Every option of a single user's recommendations can be used, except for ratings and
+/// explanations, which belong to one user
+#[derive(Debug, Deserialize)]
+pub struct GroupQuery {
+    /// Comma separated usernames or uids of every user in the group
+    pub users: Option<String>,
+    /// How to combine the taste of each user. Defaults to their average
+    pub strategy: Option<GroupStrategy>,
+    #[serde(flatten)]
+    pub options: RecommendationQuery,
+}
+
+// Read an optional query parameter, where an empty value is the same as none.
+// Forms send every field even when it is blank, so this lets them share the query.
+fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    match Option::<String>::deserialize(deserializer)?
+        .as_deref()
+        .map(str::trim)
+    {
+        None | Some("") => Ok(None),
+        Some(value) => value.parse().map(Some).map_err(de::Error::custom),
+    }
+}
+
+/// Query parameters accepted by the user and page search endpoints
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    /// Text to search for
+    pub q: Option<String>,
+    /// Maximum number of results to return
+    pub limit: Option<usize>,
+}
+
+/// Query parameters accepted by the comparison endpoints
+#[derive(Debug, Deserialize)]
+pub struct CompareQuery {
+    /// Username or uid of the first user
+    pub a: Option<String>,
+    /// Username or uid of the second user
+    pub b: Option<String>,
+}
+
+/// Body of a comparison response, describing how the taste of two users compares.
+/// Lists of pages are ordered by popularity, most votes first
+#[derive(Serialize)]
+pub struct CompareResponse {
+    /// The name of the first user
+    pub a: String,
+    /// The name of the second user
+    pub b: String,
+    /// How similar the recommender thinks the users are, from -1 to 1
+    pub similarity: f64,
+    /// The number of pages both users voted the same way on
+    pub agreements: u32,
+    /// The number of pages the users voted differently on
+    pub disagreements: u32,
+    pub both_liked: Vec<Recommendation>,
+    pub both_disliked: Vec<Recommendation>,
+    pub a_liked_b_disliked: Vec<Recommendation>,
+    pub b_liked_a_disliked: Vec<Recommendation>,
+    /// Tags both users like, the most liked by both first
+    pub shared_tags: Vec<String>,
+    pub liked_by_a_unread_by_b: Vec<Recommendation>,
+    pub liked_by_b_unread_by_a: Vec<Recommendation>,
+}
+
+/// Body of a user search response, with the best match first
+#[derive(Serialize)]
+pub struct UserSearchResponse {
+    pub users: Vec<UserMatch>,
+}
+
+/// Body of a page search response, with the best match first.
+/// Pages are in the same form as recommendations, without explanations.
+#[derive(Serialize)]
+pub struct PageSearchResponse {
+    pub pages: Vec<Recommendation>,
+}
+
+/// Body of a response to making a profile
+#[derive(Serialize)]
+pub struct CreatedProfile {
+    /// Secret needed to use the profile, sent as `Authorization: Bearer <token>`
+    pub token: String,
+}
+
+/// A profile's saved settings
+#[derive(Serialize)]
+pub struct ProfileResponse {
+    pub tags: Option<String>,
+    pub marks: Vec<MarkedPage>,
+}
+
+/// A page marked by a profile
+#[derive(Serialize)]
+pub struct MarkedPage {
+    pub pid: u64,
+    pub kind: MarkKind,
+    /// Missing if the page is not known to the recommender, or it has not loaded yet
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Values of the form on the recommendations page, kept as they were typed so that they can be
+/// sent again with every link and button on the page
+#[derive(Default)]
+pub struct RecommendationForm {
+    pub user: String,
+    pub tags: String,
+    pub any_tags: String,
+    pub exclude_tags: String,
+    pub bans: String,
+    pub diversity: String,
+    pub novelty: String,
+    pub limit: String,
+}
+
+impl From<&RecommendationQuery> for RecommendationForm {
+    fn from(params: &RecommendationQuery) -> Self {
+        let text = |value: &Option<String>| value.as_deref().unwrap_or_default().trim().to_owned();
+        let number = |value: Option<String>| value.unwrap_or_default();
+
+        RecommendationForm {
+            user: text(&params.user),
+            tags: text(&params.tags),
+            any_tags: text(&params.any_tags),
+            exclude_tags: text(&params.exclude_tags),
+            bans: text(&params.bans),
+            diversity: number(params.diversity.map(|diversity| diversity.to_string())),
+            novelty: number(params.novelty.map(|novelty| novelty.to_string())),
+            limit: number(params.limit.map(|limit| limit.to_string())),
+        }
+    }
+}
+
+impl RecommendationForm {
+    /// The bans with another page added
+    pub fn with_ban(&self, pid: u64) -> String {
+        match self.bans.is_empty() {
+            true => pid.to_string(),
+            false => format!("{} {}", self.bans, pid),
+        }
+    }
+}
+
+/// A banned page shown on the recommendations page, so that it can be unbanned
+pub struct BannedPage {
+    pub pid: u64,
+    /// The page's name, or its pid if it is not known
+    pub name: String,
+    /// The other bans, which are kept when this one is removed
+    pub other_bans: String,
+}
+
+/// Body of a request to change a profile's tag filter
+#[derive(Deserialize)]
+pub struct ProfileTagsBody {
+    /// Tag filter expression, or null to remove it
+    pub tags: Option<String>,
+}
+
+/// Body of a request to mark a page
+#[derive(Deserialize)]
+pub struct ProfileMarkBody {
+    /// Defaults to a ban
+    #[serde(default = "default_mark_kind")]
+    pub kind: MarkKind,
+}
+
+fn default_mark_kind() -> MarkKind {
+    MarkKind::Ban
+}
+
+/// A single recommended page
+#[derive(Serialize)]
+pub struct Recommendation {
+    pub name: String,
+    pub url: String,
+    pub tags: Vec<String>,
+    pub pid: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<Explanation>,
+}
+
+/// Body of a successful recommendation response
+#[derive(Serialize)]
+pub struct RecommendationsResponse {
+    pub recommendations: Vec<Recommendation>,
+    /// Number of pages which could have been recommended with the given filters
+    pub total: usize,
+    /// Position of the first recommendation in the full ranking
+    pub offset: usize,
+    /// Cursor for the next window of recommendations, if there are any more
+    pub next_cursor: Option<String>,
+}
+
+/// Settings the server was started with, as reported by the info endpoint
+#[derive(Clone, Debug, Serialize)]
+pub struct ServingOptions {
+    pub min_votes: u16,
+    pub users_to_consider: u32,
+    pub novelty: f64,
+    pub upvote_weight: f64,
+    pub downvote_weight: f64,
+    pub exclude_downvoted: bool,
+    /// Whether the recommender was loaded from a snapshot rather than the output files
+    pub snapshot: bool,
+    pub cache_size: usize,
+    pub max_concurrent: usize,
+    /// Seconds a request waits for its turn in the work pool
+    pub queue_timeout: u64,
+}
+
+/// Facts about the server which are known before the recommender is loaded
+#[derive(Debug)]
+pub struct ServerInfo {
+    pub options: ServingOptions,
+}
+
+/// Body of the info endpoint
+#[derive(Serialize)]
+pub struct InfoResponse {
+    pub version: &'static str,
+    pub ready: bool,
+    /// Unix time the scrape the data came from started, once the recommender is loaded.
+    /// Data from scrapes which did not record this does not have it
+    pub scraped_at: Option<u64>,
+    /// How much data the recommender was built from, once it is loaded
+    pub model: Option<ModelSize>,
+    pub options: ServingOptions,
+}
+
+/// A position in a ranking of recommendations.
+/// Clients only ever see this encoded, so the format can change without breaking them.
+#[derive(Debug, PartialEq)]
+pub struct Cursor {
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        format!("{:016x}{:08x}", self.offset, self.limit)
+    }
+
+    /// Reads an encoded cursor, returning None if it is not a valid one
+    pub fn decode(cursor: &str) -> Option<Cursor> {
+        if cursor.len() != 24 || !cursor.is_ascii() {
+            return None;
+        }
+
+        Some(Cursor {
+            offset: usize::from_str_radix(&cursor[0..16], 16).ok()?,
+            limit: usize::from_str_radix(&cursor[16..24], 16).ok()?,
+        })
+    }
+}
+
+/// An error returned by an API endpoint.
+/// This is sent as JSON with a matching HTTP status code.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct ApiErrorBody<'a> {
+    r#type: &'static str,
+    code: &'a str,
+    message: &'a str,
+}
+
+impl ApiError {
+    /// Creates an error with a machine-readable code and a human-readable message
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Creates a 400 error for a request which could never succeed as sent
+    pub fn bad_request(code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::BAD_REQUEST, code, message)
+    }
+
+    /// Creates a 503 error for a request made before the recommender has loaded
+    pub fn not_ready() -> ApiError {
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "NOT_READY",
+            "The server is still loading. Try again soon",
+        )
+    }
+
+    /// Creates a 404 error for something which does not exist
+    pub fn not_found(code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::NOT_FOUND, code, message)
+    }
+
+    pub fn get_status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn get_code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn get_message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ApiErrorBody {
+            r#type: "error",
+            code: self.code,
+            message: &self.message,
+        };
+
+        (self.status, Json(body)).into_response()
+    }
+}
+
+impl From<RecommenderError> for ApiError {
+    fn from(value: RecommenderError) -> Self {
+        match value {
+            RecommenderError::UserNotFound => ApiError::not_found(
+                "USER_NOT_FOUND",
+                "No user by that name or id is known to the recommender",
+            ),
+            RecommenderError::PageNotFound => {
+                ApiError::not_found("PAGE_NOT_FOUND", "No page by that id is known")
+            }
+            RecommenderError::TagFilter(reason) => ApiError::bad_request("INVALID_TAGS", reason),
+            // Anything else is a problem with the server or its data, not the request.
+            // Its details can include file paths, so they are only logged
+            _ => ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "RECOMMENDER_ERROR",
+                "The recommender failed",
+            ),
+        }
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(value: QueryRejection) -> Self {
+        ApiError::bad_request("INVALID_QUERY", value.body_text())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(value: JsonRejection) -> Self {
+        ApiError::new(value.status(), "INVALID_BODY", value.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(value: PathRejection) -> Self {
+        ApiError::bad_request("INVALID_PATH", value.body_text())
+    }
+}
+
+impl From<ProfileError> for ApiError {
+    fn from(value: ProfileError) -> Self {
+        match value {
+            ProfileError::NotFound => {
+                ApiError::not_found("PROFILE_NOT_FOUND", "No profile has that token")
+            }
+            ProfileError::TooManyMarks => ApiError::bad_request(
+                "TOO_MANY_MARKS",
+                format!(
+                    "Profiles can mark at most {} pages",
+                    super::profiles::MAX_MARKS
+                ),
+            ),
+            // Database errors are logged, and their details are not the client's concern
+            ProfileError::Database(_) => ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "PROFILE_ERROR",
+                "The profile store failed",
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recommender_error_status() {
+        let error = ApiError::from(RecommenderError::UserNotFound);
+        assert_eq!(error.get_status(), StatusCode::NOT_FOUND);
+        assert_eq!(error.get_code(), "USER_NOT_FOUND");
+
+        let error = ApiError::from(RecommenderError::Bounds);
+        assert_eq!(error.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.get_code(), "RECOMMENDER_ERROR");
+
+        // Internal errors can include file paths, so their details are never sent
+        let error = ApiError::from(RecommenderError::from(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "/srv/lotus/votes.parquet",
+        )));
+        assert_eq!(error.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.get_message(), "The recommender failed");
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = Cursor {
+            offset: 120,
+            limit: 30,
+        };
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+
+        assert_eq!(Cursor::decode(""), None);
+        assert_eq!(Cursor::decode("not a cursor at all, no!"), None);
+    }
+
+    #[test]
+    fn read_form_query() {
+        use axum::{extract::Query, http::Uri};
+
+        // Blank form fields are the same as missing ones
+        let uri: Uri = "/recommendations?user=studyGT&tags=&diversity=&limit=20&cursor="
+            .parse()
+            .unwrap();
+        let Query(params) = Query::<RecommendationQuery>::try_from_uri(&uri).unwrap();
+        assert_eq!(params.diversity, None);
+        assert_eq!(params.limit, Some(20));
+        assert_eq!(params.cursor, None);
+
+        let form = RecommendationForm::from(&params);
+        assert_eq!(form.user, "studyGT");
+        assert_eq!(form.limit, "20");
+        assert_eq!(form.with_ban(173), "173");
+
+        let uri: Uri = "/recommendations?user=studyGT&diversity=lots"
+            .parse()
+            .unwrap();
+        assert!(Query::<RecommendationQuery>::try_from_uri(&uri).is_err());
+    }
+}
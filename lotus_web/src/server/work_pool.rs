@@ -0,0 +1,164 @@
+use super::ApiError;
+use axum::http::StatusCode;
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::Semaphore, task, time};
+use tracing::{error, Span};
+
+/// Runs CPU-heavy work on tokio's blocking threads, so that it never stalls the async workers
+/// serving every other route.
+/// Only a limited amount of work runs at once. Anything else waits in a queue, and gives up
+/// with a 503 if it waits too long.
+pub struct WorkPool {
+    permits: Arc<Semaphore>,
+    max_concurrent: usize,
+    queue_timeout: Duration,
+}
+
+impl WorkPool {
+    /// Creates a pool running at most `max_concurrent` jobs at once, where each job waits at most
+    /// `queue_timeout` to start.
+    /// A limit of 0 is treated as 1.
+    pub fn new(max_concurrent: usize, queue_timeout: Duration) -> WorkPool {
+        let max_concurrent = max_concurrent.max(1);
+
+        WorkPool {
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent,
+            queue_timeout,
+        }
+    }
+
+    /// Run a job once there is room for it, returning its result.
+    /// The job runs inside the caller's span, so its logs keep the request they came from.
+    /// It keeps its place in the pool until it finishes, even if the caller stops waiting for it.
+    pub async fn run<F, T>(&self, job: F) -> Result<T, ApiError>
+    where
+        F: FnOnce() -> Result<T, ApiError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let permits = Arc::clone(&self.permits);
+        let permit = match time::timeout(self.queue_timeout, permits.acquire_owned()).await {
+            Ok(Ok(permit)) => permit,
+            // The semaphore is never closed, so this only happens on timeout
+            _ => {
+                return Err(ApiError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "SERVER_BUSY",
+                    "The server is handling too many requests. Try again soon",
+                ))
+            }
+        };
+
+        let span = Span::current();
+
+        match task::spawn_blocking(move || {
+            let _permit = permit;
+            span.in_scope(job)
+        })
+        .await
+        {
+            Ok(result) => result,
+            // A panic message can describe the server's data, so it is only logged
+            Err(e) => {
+                error!(error = %e, "Recommendation work failed");
+                Err(ApiError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "INTERNAL_ERROR",
+                    "The server failed to finish the request",
+                ))
+            }
+        }
+    }
+
+    /// Returns how many jobs are running right now
+    pub fn get_running(&self) -> usize {
+        self.max_concurrent - self.permits.available_permits()
+    }
+
+    pub fn get_max_concurrent(&self) -> usize {
+        self.max_concurrent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reject_when_full() {
+        let pool = Arc::new(WorkPool::new(1, Duration::from_millis(50)));
+
+        let busy_pool = Arc::clone(&pool);
+        let busy = tokio::spawn(async move {
+            busy_pool
+                .run(|| {
+                    std::thread::sleep(Duration::from_millis(500));
+                    Ok(())
+                })
+                .await
+        });
+
+        // Give the first job time to take the only permit
+        time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(pool.get_running(), 1);
+
+        let rejected = pool.run(|| Ok(())).await;
+        assert_eq!(
+            rejected.err().map(|e| e.get_status()),
+            Some(StatusCode::SERVICE_UNAVAILABLE)
+        );
+
+        assert!(busy.await.unwrap().is_ok());
+        assert!(pool.run(|| Ok(())).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn hide_panic_message() {
+        let pool = WorkPool::new(1, Duration::from_millis(50));
+
+        let failed = pool
+            .run::<_, ()>(|| panic!("Page in recommender but not pages list: 1234"))
+            .await
+            .expect_err("A panicking job should fail");
+        assert_eq!(failed.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            failed.get_message(),
+            "The server failed to finish the request"
+        );
+
+        // The permit is returned even though the job panicked
+        assert_eq!(pool.get_running(), 0);
+    }
+
+    #[tokio::test]
+    async fn hold_permit_after_cancel() {
+        let pool = Arc::new(WorkPool::new(1, Duration::from_millis(50)));
+
+        let (started_send, started) = std::sync::mpsc::channel();
+        let (finish, finish_recv) = std::sync::mpsc::channel::<()>();
+        let job = pool.run(move || {
+            started_send.send(()).unwrap();
+            finish_recv.recv().unwrap();
+            Ok(())
+        });
+
+        // Start the job, then stop waiting for it like a disconnected client would
+        let cancelled = time::timeout(Duration::from_millis(100), job).await;
+        assert!(cancelled.is_err());
+        started.recv().unwrap();
+
+        // The job is still running, so it still holds the only permit
+        assert_eq!(pool.get_running(), 1);
+        let rejected = pool.run(|| Ok(())).await;
+        assert_eq!(
+            rejected.err().map(|e| e.get_status()),
+            Some(StatusCode::SERVICE_UNAVAILABLE)
+        );
+
+        finish.send(()).unwrap();
+        // Give the job time to finish and return its permit
+        time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(pool.get_running(), 0);
+        assert!(pool.run(|| Ok(())).await.is_ok());
+    }
+}
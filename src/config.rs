@@ -0,0 +1,605 @@
+use crate::OutputFiles;
+use clap::{builder::BoolishValueParser, ArgAction, Args, Parser};
+use serde::Deserialize;
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    thread,
+};
+
+/// Config file read when none is given, if it exists
+pub const DEFAULT_CONFIG_FILE: &str = "lotus.toml";
+
+// Defaults for the scraper
+pub const DEFAULT_CONCURRENT_REQUESTS: u8 = 8;
+pub const DEFAULT_DOWNLOAD_DELAY: u64 = 0;
+
+// Defaults for the web server
+pub const DEFAULT_ADDRESS: &str = "0.0.0.0:3000";
+pub const DEFAULT_STATIC_DIR: &str = "lotus_web/files";
+pub const DEFAULT_MIN_VOTES: u16 = 10;
+pub const DEFAULT_USERS_TO_CONSIDER: u32 = 30;
+pub const DEFAULT_NOVELTY: f64 = 0f64;
+pub const DEFAULT_UPVOTE_WEIGHT: f64 = 1f64;
+pub const DEFAULT_DOWNVOTE_WEIGHT: f64 = 1f64;
+pub const DEFAULT_CACHE_SIZE: usize = 128;
+pub const DEFAULT_QUEUE_TIMEOUT: u64 = 10;
+
+/// A problem with the configuration, found before anything is run
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file could not be read
+    Read(PathBuf, io::Error),
+    /// The config file is not valid TOML, or has unknown or mistyped settings
+    Parse(PathBuf, toml::de::Error),
+    /// A setting has a value which cannot be used
+    Invalid(&'static str, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => {
+                write!(f, "Could not read config file {}: {}", path.display(), e)
+            }
+            ConfigError::Parse(path, e) => {
+                write!(f, "Invalid config file {}: {}", path.display(), e)
+            }
+            ConfigError::Invalid(setting, reason) => write!(f, "Invalid {}: {}", setting, reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Locations of the output files, shared by both programs
+#[derive(Args, Debug, Default)]
+pub struct OutputArgs {
+    /// File that articles are saved to [default: ./output/articles.parquet]
+    #[arg(short = 'a', long, env = "LOTUS_ARTICLE_FILE", value_name = "FILE")]
+    pub article_file: Option<String>,
+    /// File that tags are saved to [default: ./output/tags.parquet]
+    #[arg(short = 't', long, env = "LOTUS_TAGS_FILE", value_name = "FILE")]
+    pub tags_file: Option<String>,
+    /// File that users are saved to [default: ./output/users.parquet]
+    #[arg(short = 'u', long, env = "LOTUS_USERS_FILE", value_name = "FILE")]
+    pub users_file: Option<String>,
+    /// File that votes are saved to [default: ./output/votes.parquet]
+    #[arg(short = 'v', long, env = "LOTUS_VOTES_FILE", value_name = "FILE")]
+    pub votes_file: Option<String>,
+}
+
+/// How logs are written, shared by both programs
+#[derive(Args, Debug, Default)]
+pub struct LogArgs {
+    /// Which logs are written, as a level (error, warn, info, debug, trace) or per-module filter.
+    /// If not given, the RUST_LOG environment variable is used [default: info]
+    #[arg(short = 'g', long, env = "LOTUS_LOG_LEVEL", value_name = "FILTER")]
+    pub log_level: Option<String>,
+    /// Write logs as JSON objects, one per line, instead of plain text.
+    /// `--log-json=false` turns this off when the config file turns it on
+    #[arg(short = 'j', long, env = "LOTUS_LOG_JSON", value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true", action = ArgAction::Set, value_parser = BoolishValueParser::new())]
+    pub log_json: Option<bool>,
+}
+
+/// Command line arguments for the scraper
+#[derive(Parser, Debug)]
+#[command(
+    name = "lotus_scrape",
+    version,
+    about = "Scrapes the SCP wiki for recommendation data"
+)]
+pub struct ScrapeCli {
+    /// TOML file to read settings from. Command line arguments and environment variables
+    /// override it [default: ./lotus.toml, if it exists]
+    #[arg(short = 'C', long, env = "LOTUS_CONFIG", value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    #[command(flatten)]
+    pub outputs: OutputArgs,
+    /// Number of articles to fetch from the wiki. Each article takes about 2 web requests to get
+    /// [default: maximum]
+    #[arg(short = 'l', long, env = "LOTUS_ARTICLE_LIMIT", value_name = "COUNT")]
+    pub article_limit: Option<usize>,
+    /// Number of requests to make at one time (the number of additional threads to make)
+    /// [default: 8]
+    #[arg(
+        short = 'r',
+        long,
+        env = "LOTUS_CONCURRENT_REQUESTS",
+        value_name = "COUNT"
+    )]
+    pub concurrent_requests: Option<u8>,
+    /// Additional approximate delay between requests, in milliseconds.
+    /// This time is added in between each web request [default: 0]
+    #[arg(
+        short = 'd',
+        long,
+        env = "LOTUS_DOWNLOAD_DELAY",
+        value_name = "MILLISECONDS"
+    )]
+    pub download_delay: Option<u64>,
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+/// Command line arguments for the web server
+#[derive(Parser, Debug)]
+#[command(
+    name = "lotus_web",
+    version,
+    about = "Serves recommendations for the SCP wiki",
+    allow_negative_numbers = true
+)]
+pub struct WebCli {
+    /// TOML file to read settings from. Command line arguments and environment variables
+    /// override it [default: ./lotus.toml, if it exists]
+    #[arg(short = 'C', long, env = "LOTUS_CONFIG", value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    #[command(flatten)]
+    pub outputs: OutputArgs,
+    /// Load the recommender from a snapshot instead of the output files, which is much faster.
+    /// The snapshot must have been built with the same minimum votes and vote weights
+    #[arg(short = 's', long, env = "LOTUS_SNAPSHOT", value_name = "FILE")]
+    pub snapshot: Option<String>,
+    /// Build the recommender from the output files, save a snapshot of it, then exit
+    #[arg(short = 'b', long, value_name = "FILE")]
+    pub build_snapshot: Option<String>,
+    /// IP address and port to listen for connections on [default: 0.0.0.0:3000]
+    #[arg(short = 'i', long, env = "LOTUS_ADDRESS")]
+    pub address: Option<String>,
+    /// Directory the site's scripts, styles, and images are served from [default:
+    /// lotus_web/files]
+    #[arg(short = 'f', long, env = "LOTUS_STATIC_DIR", value_name = "DIR")]
+    pub static_dir: Option<String>,
+    /// SQLite database to keep user profiles in, so that bans and settings can be shared between
+    /// devices. It is created if it does not exist. Without one, profiles are turned off
+    #[arg(long, env = "LOTUS_PROFILES_DB", value_name = "FILE")]
+    pub profiles_db: Option<String>,
+    /// Minimum number of votes each user must have to be included in the recommender.
+    /// Setting this too low slows recommendations and uses a lot of memory, but users with
+    /// fewer votes cannot be recommended to [default: 10]
+    #[arg(short = 'm', long, env = "LOTUS_MIN_VOTES", value_name = "COUNT")]
+    pub min_votes: Option<u16>,
+    /// Number of similar users to consider for each recommendation.
+    /// Setting this too high leads to more popularity bias and slightly slower recommendations,
+    /// but takes more opinions into account [default: 30]
+    #[arg(
+        short = 'c',
+        long,
+        env = "LOTUS_USERS_TO_CONSIDER",
+        value_name = "COUNT"
+    )]
+    pub users_to_consider: Option<u32>,
+    /// How much less popular pages are preferred when a request does not specify it.
+    /// Page weights are divided by their vote count raised to this power, from 0 to 1, so 0
+    /// disables it [default: 0]
+    #[arg(short = 'n', long, env = "LOTUS_NOVELTY")]
+    pub novelty: Option<f64>,
+    /// How much upvotes count for, both when finding similar users and weighting pages.
+    /// This is part of the model, so snapshots must be rebuilt after changing it [default: 1]
+    #[arg(short = 'w', long, env = "LOTUS_UPVOTE_WEIGHT", value_name = "WEIGHT")]
+    pub upvote_weight: Option<f64>,
+    /// How much downvotes count for, as a positive number.
+    /// This is part of the model, so snapshots must be rebuilt after changing it [default: 1]
+    #[arg(
+        short = 'd',
+        long,
+        env = "LOTUS_DOWNVOTE_WEIGHT",
+        value_name = "WEIGHT"
+    )]
+    pub downvote_weight: Option<f64>,
+    /// Remove pages from recommendations if most of the similar users who voted on them
+    /// downvoted them. `--exclude-downvoted=false` turns this off when the config file turns it on
+    #[arg(short = 'x', long, env = "LOTUS_EXCLUDE_DOWNVOTED", value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true", action = ArgAction::Set, value_parser = BoolishValueParser::new())]
+    pub exclude_downvoted: Option<bool>,
+    /// Number of users' page rankings kept in memory, so their later requests are faster.
+    /// Each ranking holds a weight for every page, so raising this uses more memory
+    /// [default: 128]
+    #[arg(short = 'r', long, env = "LOTUS_CACHE_SIZE", value_name = "COUNT")]
+    pub cache_size: Option<usize>,
+    /// Number of recommendations which can be worked on at once. Other requests wait for their
+    /// turn [default: number of CPU threads]
+    #[arg(short = 'p', long, env = "LOTUS_MAX_CONCURRENT", value_name = "COUNT")]
+    pub max_concurrent: Option<usize>,
+    /// Seconds a request waits for its turn before the server reports it is busy [default: 10]
+    #[arg(short = 'q', long, env = "LOTUS_QUEUE_TIMEOUT", value_name = "SECONDS")]
+    pub queue_timeout: Option<u64>,
+    /// Let another server listen on the same address at the same time, so a new server can
+    /// start before the old one stops. With this, the server only starts listening once it is
+    /// ready to serve recommendations. Ignored when the socket is passed in by systemd.
+    /// `--reuse-port=false` turns this off when the config file turns it on
+    #[arg(short = 'o', long, env = "LOTUS_REUSE_PORT", value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true", action = ArgAction::Set, value_parser = BoolishValueParser::new())]
+    pub reuse_port: Option<bool>,
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+// Layout of the config file. Every setting is optional, and unknown settings are rejected so
+// that typos are not silently ignored.
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    output: FileOutputs,
+    log: FileLog,
+    scrape: FileScrape,
+    web: FileWeb,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileOutputs {
+    article_file: Option<String>,
+    tags_file: Option<String>,
+    users_file: Option<String>,
+    votes_file: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileLog {
+    level: Option<String>,
+    json: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileScrape {
+    article_limit: Option<usize>,
+    concurrent_requests: Option<u8>,
+    download_delay: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileWeb {
+    address: Option<String>,
+    static_dir: Option<String>,
+    profiles_db: Option<String>,
+    snapshot: Option<String>,
+    min_votes: Option<u16>,
+    users_to_consider: Option<u32>,
+    novelty: Option<f64>,
+    upvote_weight: Option<f64>,
+    downvote_weight: Option<f64>,
+    exclude_downvoted: Option<bool>,
+    cache_size: Option<usize>,
+    max_concurrent: Option<usize>,
+    queue_timeout: Option<u64>,
+    reuse_port: Option<bool>,
+}
+
+/// How logs are written
+#[derive(Clone, Debug)]
+pub struct LogConfig {
+    /// Log filter, or None to use RUST_LOG
+    pub level: Option<String>,
+    pub json: bool,
+}
+
+/// Every setting for the scraper
+#[derive(Clone, Debug)]
+pub struct ScrapeConfig {
+    pub outputs: OutputFiles,
+    pub article_limit: usize,
+    pub concurrent_requests: u8,
+    /// Milliseconds between requests
+    pub download_delay: u64,
+    pub log: LogConfig,
+}
+
+/// Every setting for the web server
+#[derive(Clone, Debug)]
+pub struct WebConfig {
+    pub outputs: OutputFiles,
+    pub snapshot: Option<String>,
+    pub build_snapshot: Option<String>,
+    pub address: String,
+    pub static_dir: String,
+    /// SQLite database user profiles are kept in, if profiles are turned on
+    pub profiles_db: Option<String>,
+    pub min_votes: u16,
+    pub users_to_consider: u32,
+    pub novelty: f64,
+    pub upvote_weight: f64,
+    pub downvote_weight: f64,
+    pub exclude_downvoted: bool,
+    pub cache_size: usize,
+    pub max_concurrent: usize,
+    /// Seconds a request waits for its turn
+    pub queue_timeout: u64,
+    pub reuse_port: bool,
+    pub log: LogConfig,
+}
+
+impl ScrapeConfig {
+    /// Read the scraper's settings from the command line, environment, and config file.
+    /// Exits with a usage message if the command line cannot be parsed.
+    pub fn load() -> Result<ScrapeConfig, ConfigError> {
+        Self::from_cli(ScrapeCli::parse())
+    }
+
+    /// Fill in anything not given on the command line from the config file, then the defaults
+    pub fn from_cli(cli: ScrapeCli) -> Result<ScrapeConfig, ConfigError> {
+        let file = read_config_file(cli.config.as_deref())?;
+
+        let config = ScrapeConfig {
+            outputs: merge_outputs(cli.outputs, file.output),
+            article_limit: cli
+                .article_limit
+                .or(file.scrape.article_limit)
+                .unwrap_or(usize::MAX),
+            concurrent_requests: cli
+                .concurrent_requests
+                .or(file.scrape.concurrent_requests)
+                .unwrap_or(DEFAULT_CONCURRENT_REQUESTS),
+            download_delay: cli
+                .download_delay
+                .or(file.scrape.download_delay)
+                .unwrap_or(DEFAULT_DOWNLOAD_DELAY),
+            log: merge_log(cli.log, file.log),
+        };
+
+        if config.concurrent_requests == 0 {
+            return Err(ConfigError::Invalid(
+                "concurrent-requests",
+                String::from("must be at least 1"),
+            ));
+        }
+
+        Ok(config)
+    }
+}
+
+impl WebConfig {
+    /// Read the web server's settings from the command line, environment, and config file.
+    /// Exits with a usage message if the command line cannot be parsed.
+    pub fn load() -> Result<WebConfig, ConfigError> {
+        Self::from_cli(WebCli::parse())
+    }
+
+    /// Fill in anything not given on the command line from the config file, then the defaults
+    pub fn from_cli(cli: WebCli) -> Result<WebConfig, ConfigError> {
+        let file = read_config_file(cli.config.as_deref())?;
+        let web = file.web;
+
+        let config = WebConfig {
+            outputs: merge_outputs(cli.outputs, file.output),
+            snapshot: cli.snapshot.or(web.snapshot),
+            build_snapshot: cli.build_snapshot,
+            address: cli
+                .address
+                .or(web.address)
+                .unwrap_or_else(|| String::from(DEFAULT_ADDRESS)),
+            static_dir: cli
+                .static_dir
+                .or(web.static_dir)
+                .unwrap_or_else(|| String::from(DEFAULT_STATIC_DIR)),
+            profiles_db: cli.profiles_db.or(web.profiles_db),
+            min_votes: cli.min_votes.or(web.min_votes).unwrap_or(DEFAULT_MIN_VOTES),
+            users_to_consider: cli
+                .users_to_consider
+                .or(web.users_to_consider)
+                .unwrap_or(DEFAULT_USERS_TO_CONSIDER),
+            novelty: cli.novelty.or(web.novelty).unwrap_or(DEFAULT_NOVELTY),
+            upvote_weight: cli
+                .upvote_weight
+                .or(web.upvote_weight)
+                .unwrap_or(DEFAULT_UPVOTE_WEIGHT),
+            downvote_weight: cli
+                .downvote_weight
+                .or(web.downvote_weight)
+                .unwrap_or(DEFAULT_DOWNVOTE_WEIGHT),
+            exclude_downvoted: cli
+                .exclude_downvoted
+                .or(web.exclude_downvoted)
+                .unwrap_or(false),
+            cache_size: cli
+                .cache_size
+                .or(web.cache_size)
+                .unwrap_or(DEFAULT_CACHE_SIZE),
+            max_concurrent: cli
+                .max_concurrent
+                .or(web.max_concurrent)
+                .unwrap_or_else(|| thread::available_parallelism().map_or(1, |count| count.get())),
+            queue_timeout: cli
+                .queue_timeout
+                .or(web.queue_timeout)
+                .unwrap_or(DEFAULT_QUEUE_TIMEOUT),
+            reuse_port: cli.reuse_port.or(web.reuse_port).unwrap_or(false),
+            log: merge_log(cli.log, file.log),
+        };
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if !(0f64..=1f64).contains(&self.novelty) {
+            return Err(ConfigError::Invalid(
+                "novelty",
+                String::from("must be from 0 to 1"),
+            ));
+        }
+
+        for (setting, weight) in [
+            ("upvote-weight", self.upvote_weight),
+            ("downvote-weight", self.downvote_weight),
+        ] {
+            if !weight.is_finite() || weight <= 0f64 {
+                return Err(ConfigError::Invalid(
+                    setting,
+                    String::from("must be more than 0"),
+                ));
+            }
+        }
+
+        for (setting, count) in [
+            ("cache-size", self.cache_size),
+            ("max-concurrent", self.max_concurrent),
+        ] {
+            if count == 0 {
+                return Err(ConfigError::Invalid(
+                    setting,
+                    String::from("must be at least 1"),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Read the given config file, or the default one if it exists.
+// A missing file is only an error if it was asked for.
+fn read_config_file(path: Option<&Path>) -> Result<FileConfig, ConfigError> {
+    let path = match path {
+        Some(path) => path,
+        None if Path::new(DEFAULT_CONFIG_FILE).exists() => Path::new(DEFAULT_CONFIG_FILE),
+        None => return Ok(FileConfig::default()),
+    };
+
+    let text = fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_owned(), e))?;
+
+    toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_owned(), e))
+}
+
+fn merge_outputs(cli: OutputArgs, file: FileOutputs) -> OutputFiles {
+    let defaults = OutputFiles::new();
+
+    OutputFiles {
+        article_output: cli
+            .article_file
+            .or(file.article_file)
+            .unwrap_or(defaults.article_output),
+        tags_output: cli
+            .tags_file
+            .or(file.tags_file)
+            .unwrap_or(defaults.tags_output),
+        users_output: cli
+            .users_file
+            .or(file.users_file)
+            .unwrap_or(defaults.users_output),
+        votes_output: cli
+            .votes_file
+            .or(file.votes_file)
+            .unwrap_or(defaults.votes_output),
+    }
+}
+
+fn merge_log(cli: LogArgs, file: FileLog) -> LogConfig {
+    LogConfig {
+        level: cli.log_level.or(file.level),
+        json: cli.log_json.or(file.json).unwrap_or(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn write_config(name: &str, text: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("lotus_{}_{}.toml", name, std::process::id()));
+        fs::write(&path, text).expect("Config file not written");
+        path
+    }
+
+    #[test]
+    fn command_line_overrides_file() {
+        let path = write_config(
+            "override",
+            "[output]\nvotes_file = \"file_votes.parquet\"\n\n[log]\njson = true\n\n\
+             [web]\nmin_votes = 5\nnovelty = 0.5\nexclude_downvoted = true\nreuse_port = true\n",
+        );
+        let config_file = path.to_str().unwrap();
+
+        let cli =
+            WebCli::try_parse_from(["lotus_web", "--config", config_file, "--min-votes", "20"])
+                .expect("Arguments not parsed");
+        let config = WebConfig::from_cli(cli).expect("Config not loaded");
+
+        assert_eq!(config.min_votes, 20);
+        assert_eq!(config.novelty, 0.5);
+        assert_eq!(config.outputs.votes_output, "file_votes.parquet");
+        assert_eq!(config.users_to_consider, DEFAULT_USERS_TO_CONSIDER);
+        assert!(config.exclude_downvoted && config.reuse_port && config.log.json);
+
+        // Flags the file turns on can be turned off again
+        let cli = WebCli::try_parse_from([
+            "lotus_web",
+            "--config",
+            config_file,
+            "--exclude-downvoted=false",
+            "--reuse-port=false",
+            "-j=false",
+        ])
+        .expect("Arguments not parsed");
+        let config = WebConfig::from_cli(cli).expect("Config not loaded");
+        fs::remove_file(path).unwrap();
+
+        assert!(!config.exclude_downvoted && !config.reuse_port && !config.log.json);
+
+        // Flags without a value still turn things on
+        let cli = WebCli::try_parse_from(["lotus_web", "-x", "--reuse-port", "-j"])
+            .expect("Arguments not parsed");
+        let config = WebConfig::from_cli(cli).expect("Config not loaded");
+        assert!(config.exclude_downvoted && config.reuse_port && config.log.json);
+    }
+
+    #[test]
+    fn help_shows_defaults() {
+        use clap::CommandFactory;
+
+        let help = WebCli::command()
+            .term_width(0)
+            .render_long_help()
+            .to_string();
+        for default in [
+            DEFAULT_ADDRESS.to_string(),
+            DEFAULT_STATIC_DIR.to_string(),
+            DEFAULT_MIN_VOTES.to_string(),
+            DEFAULT_USERS_TO_CONSIDER.to_string(),
+            DEFAULT_CACHE_SIZE.to_string(),
+            DEFAULT_QUEUE_TIMEOUT.to_string(),
+        ] {
+            assert!(
+                help.contains(&format!("[default: {}]", default)),
+                "Help does not show default {}",
+                default
+            );
+        }
+
+        let help = ScrapeCli::command()
+            .term_width(0)
+            .render_long_help()
+            .to_string();
+        assert!(help.contains(&format!("[default: {}]", DEFAULT_CONCURRENT_REQUESTS)));
+    }
+
+    #[test]
+    fn reject_bad_config() {
+        let path = write_config("unknown", "[web]\nmin_vote = 5\n");
+        let cli = WebCli::try_parse_from(["lotus_web", "-C", path.to_str().unwrap()]).unwrap();
+        let result = WebConfig::from_cli(cli);
+        fs::remove_file(path).unwrap();
+        assert!(matches!(result, Err(ConfigError::Parse(_, _))));
+
+        for novelty in ["-1", "1.5", "NaN"] {
+            let cli = WebCli::try_parse_from(["lotus_web", "--novelty", novelty]).unwrap();
+            assert!(matches!(
+                WebConfig::from_cli(cli),
+                Err(ConfigError::Invalid("novelty", _))
+            ));
+        }
+
+        let cli = ScrapeCli::try_parse_from(["lotus_scrape", "-r", "0"]).unwrap();
+        assert!(matches!(
+            ScrapeConfig::from_cli(cli),
+            Err(ConfigError::Invalid("concurrent-requests", _))
+        ));
+
+        assert!(WebCli::try_parse_from(["lotus_web", "--min-votes", "lots"]).is_err());
+    }
+}
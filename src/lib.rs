@@ -1,5 +1,11 @@
 use const_format::formatcp;
 
+pub mod config;
+mod logging;
+
+pub use config::{ConfigError, ScrapeConfig, WebConfig};
+pub use logging::init_logging;
+
 // Directory where output files can be found
 const OUTPUT_DIR: &str = "./output";
 
@@ -10,6 +16,10 @@ const TAGS_OUTPUT: &str = formatcp!("{}/tags.parquet", OUTPUT_DIR);
 const USERS_OUTPUT: &str = formatcp!("{}/users.parquet", OUTPUT_DIR);
 const VOTES_OUTPUT: &str = formatcp!("{}/votes.parquet", OUTPUT_DIR);
 
+/// Key of the parquet metadata which holds when the scrape that made an output file started, as
+/// a unix timestamp in seconds. The scraper writes it to every output file
+pub const SCRAPED_AT_KEY: &str = "lotus.scraped_at";
+
 #[derive(Clone, Debug)]
 pub struct OutputFiles {
     pub article_output: String,
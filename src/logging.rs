@@ -0,0 +1,38 @@
+use std::io::{self, IsTerminal};
+use tracing_subscriber::{fmt, EnvFilter};
+
+/// Level to log at when none is given and `RUST_LOG` is not set
+const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Start writing logs to stderr.
+/// The filter is the given level if there is one, then `RUST_LOG`, then `info`. Filters use the
+/// `EnvFilter` syntax, so they can be a single level or per-module levels like
+/// `info,lotus_web::recommender=debug`.
+/// JSON output writes one object per line, with the fields of every open span included.
+pub fn init_logging(level: Option<&str>, json: bool) -> Result<(), String> {
+    let filter = match level {
+        Some(level) => EnvFilter::try_new(level),
+        None => {
+            EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(DEFAULT_LOG_LEVEL))
+        }
+    }
+    .map_err(|e| format!("Invalid log level: {}", e))?;
+
+    // Colors are only useful to people, not to whatever logs are piped into
+    let builder = fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal());
+
+    let result = if json {
+        builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .try_init()
+    } else {
+        builder.try_init()
+    };
+
+    result.map_err(|e| format!("Logging could not be started: {}", e))
+}
@@ -32,12 +32,33 @@ if [ $? -eq 1 ]; then
     return;
 fi
 
-# Close the server, if open
-pkill lotus_web
+# Remember the running server, so it can be stopped once its replacement is ready
+OLD_SERVER=$(pgrep -x lotus_web)
 
-# Start the server, outputting to console and to a log file
+# Start the new server alongside the old one, outputting to console and to a log file.
+# With --reuse-port, it only starts listening once it can serve recommendations.
 LOG_NAME=$(date +"%Y-%m-%d+%T")
-./lotus_web 2>&1 | tee "$SERVER_LOGS/$LOG_NAME"
+SERVER_LOG="$SERVER_LOGS/$LOG_NAME"
+./lotus_web --reuse-port > >(tee "$SERVER_LOG") 2>&1 &
+NEW_SERVER=$!
+
+until grep -q "Web server listening" "$SERVER_LOG" 2>/dev/null; do
+    # If the new server stops before it is ready, then something has gone wrong
+    # and it should be looked at by an admin!
+    # Keep the current server running until they do.
+    if ! kill -0 $NEW_SERVER 2>/dev/null; then
+        return;
+    fi
+    sleep 1
+done
+
+# Stop the old server. It finishes any requests it already has before exiting,
+# while new connections go to the new server
+if [ -n "$OLD_SERVER" ]; then
+    kill -TERM $OLD_SERVER
+fi
+
+wait $NEW_SERVER
 
 # If the above fails, then something has gone wrong 
 # and it should be looked at by an admin!